@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    embed_locales();
+    embed_git_hash();
+}
+
+// Embed every i18n/<locale>.ftl so a new translation needs no code change
+fn embed_locales() {
+    println!("cargo:rerun-if-changed=i18n");
+
+    let mut locales: Vec<_> = fs::read_dir("i18n")
+        .expect("Failed to read the i18n directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ftl"))
+        .collect();
+    locales.sort();
+
+    let mut code = String::from("const LOCALES: &[(&str, &str)] = &[\n");
+    for path in &locales {
+        let locale = path.file_stem().unwrap().to_string_lossy();
+        let path = fs::canonicalize(path).unwrap();
+        code.push_str(&format!(
+            "    ({:?}, include_str!({:?})),\n",
+            locale,
+            path.display().to_string()
+        ));
+    }
+    code.push_str("];\n");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("locales.rs"), code).unwrap();
+}
+
+// The commit shown by --version. Builds from a release tarball have no .git,
+// so packagers may pass WARP_TASKBAR_GIT_HASH instead.
+fn embed_git_hash() {
+    println!("cargo:rerun-if-env-changed=WARP_TASKBAR_GIT_HASH");
+    for path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let hash = env::var("WARP_TASKBAR_GIT_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()?;
+            let hash = String::from_utf8(output.stdout).ok()?.trim().to_string();
+            (output.status.success() && !hash.is_empty()).then_some(hash)
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=WARP_TASKBAR_GIT_HASH={}", hash);
+}
@@ -0,0 +1,131 @@
+# English labels, also used for any message missing from another locale.
+#
+# To add a language, copy this file to i18n/<locale>.ftl (e.g. de.ftl or
+# pt-BR.ftl) and translate the text after each "=". Variables like
+# { $status } are filled in at runtime and must be kept.
+
+## Menu
+
+menu-status = Status: { $status }
+menu-status-uptime = Status: { $status } for { $uptime }
+menu-account = Account: { $account }
+menu-account-unknown = unknown
+menu-start-daemon = Start WARP Service
+menu-register-device = Register This Device
+menu-colo = Via: { $colo }
+menu-expiry-warning = Warning: { $reason }
+menu-warp-cli-not-found = warp-cli not found: install it or set WARP_CLI_PATH
+menu-connect = Warp Connect
+menu-disconnect = Warp Disconnect
+menu-reconnect = Warp Reconnect
+menu-pause = Pause for { $duration }
+menu-paused = Paused, reconnecting in { $remaining }
+menu-resume = Resume now
+menu-status-command = Warp Status
+menu-refresh = Refresh
+menu-check-connectivity = Check Connectivity
+menu-always-on = Always-On
+menu-set-mode = Set Mode: { $mode }
+menu-managed = { $item } (managed)
+menu-proxy = Proxy
+menu-current-mode = Mode: { $mode }
+menu-current-mode-unknown = Mode: unknown
+menu-proxy-port = Port: { $port }
+menu-proxy-port-unknown = Port: unknown
+menu-use-proxy-port = Use Port { $port }
+menu-dns-families = DNS Families
+menu-dns-endpoint-menu = DNS Endpoint
+menu-dns-endpoint = Endpoint: { $endpoint }
+menu-dns-endpoint-default = Endpoint: default
+menu-set-dns-endpoint = Set Endpoint
+menu-clear-dns-endpoint = Reset Endpoint
+menu-virtual-network = Virtual Network
+menu-statistics = Statistics
+menu-custom = Custom
+menu-on-startup = On Startup
+menu-set-mode-menu = Set Mode
+menu-other-menu = Other
+menu-other = Other: warp-cli { $command }
+menu-other-command = warp-cli { $command }
+menu-refresh-rate = Refresh Rate
+menu-refresh-rate-secs = { $secs }s
+menu-open-warp-dir = Open WARP Folder
+menu-copy-diagnostics = Copy Diagnostics
+menu-about = About
+menu-quit = Quit
+
+## Tray icon tooltip
+
+tooltip-default = warp-cli wrapper
+tooltip-warp-cli-not-found = warp-cli not found
+tooltip-status = WARP: { $status }
+tooltip-status-mode = WARP: { $status } ({ $mode })
+
+## Connection status
+
+status-connected = Connected
+status-captive-portal = Connected (sign-in needed)
+status-connecting = Connecting
+status-disconnected = Disconnected
+status-unable-to-connect = Unable to connect
+status-registration-missing = Not registered
+status-daemon-not-running = Service not running
+status-unknown = Unknown
+
+## Notifications
+
+notify-command-succeeded = warp-cli { $command } succeeded
+notify-command-failed = warp-cli { $command } failed
+notify-command-failed-reason = warp-cli { $command } failed ({ $reason })
+notify-failed-with-error = error
+notify-failed-with-exit-code = exit code { $code }
+notify-failed-with-signal = terminated by a signal
+notify-invalid-dns-endpoint = Invalid DNS endpoint
+notify-invalid-dns-endpoint-body = { $input } is not a Gateway DoH subdomain or URL
+notify-start-daemon-failed = Failed to start the WARP service
+notify-mode-connected = WARP connected
+notify-mode-connected-body = Connected in { $mode } mode
+notify-upgrade-reapplied = warp-cli was upgraded
+notify-upgrade-reapplied-body = Re-applied the { $mode } mode after the upgrade to { $version }
+notify-reconnect-timed-out = WARP reconnect timed out
+notify-reconnect-timed-out-body = Still not disconnected after { $secs } seconds
+notify-reconnected = WARP reconnected
+notify-reconnected-body = Disconnected and connected again
+notify-protected = You are protected
+notify-protected-body = Traffic goes through WARP (warp={ $value })
+notify-not-protected = Not protected
+notify-not-protected-body = Traffic doesn't go through WARP (warp={ $value })
+notify-paused = WARP paused
+notify-paused-body = Reconnecting in { $duration }
+notify-resumed = WARP resumed
+notify-resumed-body = Connected again
+notify-report-generated = Report generated
+notify-report-saved = Report saved to { $path }
+notify-open-folder = Open folder
+notify-reconnect = Reconnect
+notify-warp-dir-not-found = WARP folder not found
+notify-warp-dir-not-found-body = { $dir } doesn't exist; set warp_dir in the config
+notify-open-warp-dir-failed = Failed to open the WARP folder
+notify-diagnostics = WARP diagnostics
+notify-diagnostics-copied = Copied to the clipboard
+notify-diagnostics-saved = Copied to the clipboard and saved to { $path }
+notify-connectivity-problem = WARP connectivity problem
+notify-connectivity-problem-body = WARP is connected but { $addresses } is unreachable. This may be a network or IPv6 issue; try reconnecting.
+notify-refresh-rate-not-saved = Refresh rate not saved
+notify-captive-portal = Sign in to the network
+notify-captive-portal-body = This network has a captive portal. Open a browser and sign in to use WARP.
+notify-config-ignored = warp-taskbar config ignored
+notify-config-ignored-body = Using the defaults until it is fixed.
+notify-start-failed = warp-taskbar could not start
+notify-gtk-failed = Failed to initialize GTK: { $error }
+
+## Dialogs
+
+dialog-confirm-command = Run warp-cli { $command }?
+dialog-start-daemon = Start the WARP service? This needs administrator rights.
+dialog-dns-endpoint = Gateway DoH subdomain or URL for DNS queries:
+dialog-about-title = About warp-taskbar
+dialog-about = Tray icon for Cloudflare WARP
+dialog-yes = Yes
+dialog-no = No
+dialog-close = Close
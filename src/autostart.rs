@@ -0,0 +1,202 @@
+use crate::config::config_home;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SERVICE_NAME: &str = "warp-taskbar.service";
+const DESKTOP_ENTRY_NAME: &str = "warp-taskbar.desktop";
+
+fn not_found(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("can't find {}", what))
+}
+
+fn service_path() -> io::Result<PathBuf> {
+    config_home()
+        .map(|dir| dir.join("systemd").join("user").join(SERVICE_NAME))
+        .ok_or_else(|| not_found("the config directory (HOME is unset)"))
+}
+
+// systemd splits ExecStart on whitespace unless the path is quoted, and
+// expands % specifiers and $ variables anywhere in it
+fn exec_start(exe: &Path) -> String {
+    let exe = exe
+        .display()
+        .to_string()
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if exe.contains(char::is_whitespace) {
+        format!("\"{}\"", exe.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        exe
+    }
+}
+
+// Tied to graphical-session.target so the tray starts once there is a panel
+// to show it in and stops with the session. SIGTERM from `systemctl stop`
+// quits the same way as the Quit item.
+fn service_unit(exe: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Cloudflare WARP tray icon\n\
+         PartOf=graphical-session.target\n\
+         After=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=graphical-session.target\n",
+        exec_start(exe)
+    )
+}
+
+// Writes the unit for the running binary; enabling it is left to the user
+pub fn install_service() -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let path = service_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, service_unit(&exe))?;
+    println!("Wrote {}", path.display());
+    println!("Start it now and with every login:");
+    println!("    systemctl --user daemon-reload");
+    println!("    systemctl --user enable --now {}", SERVICE_NAME);
+    Ok(())
+}
+
+fn systemctl(args: &[&str]) {
+    // Without a user manager there is nothing to stop or reload
+    let _ = Command::new("systemctl").arg("--user").args(args).status();
+}
+
+pub fn uninstall_service() -> io::Result<()> {
+    let path = service_path()?;
+    if !path.exists() {
+        println!("{} is not installed", path.display());
+        return Ok(());
+    }
+    systemctl(&["disable", "--now", SERVICE_NAME]);
+    fs::remove_file(&path)?;
+    systemctl(&["daemon-reload"]);
+    println!("Removed {}", path.display());
+    Ok(())
+}
+
+fn autostart_dir() -> io::Result<PathBuf> {
+    config_home()
+        .map(|dir| dir.join("autostart"))
+        .ok_or_else(|| not_found("the config directory (HOME is unset)"))
+}
+
+// Exec= quoting from the desktop entry spec: paths with reserved characters
+// go in double quotes, then backslashes are escaped once more for the
+// string value. A literal % has to be doubled either way.
+fn desktop_exec(exe: &Path) -> String {
+    let exe = exe.display().to_string().replace('%', "%%");
+    let reserved = |c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c);
+    if !exe.contains(reserved) {
+        return exe;
+    }
+    let mut quoted = String::from("\"");
+    for c in exe.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted.replace('\\', "\\\\")
+}
+
+fn desktop_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=WARP Taskbar\n\
+         Comment=Tray icon for Cloudflare WARP\n\
+         Exec={}\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        desktop_exec(exe)
+    )
+}
+
+// Another entry in the autostart folder that already launches this binary,
+// e.g. one made by hand or by a desktop's startup settings
+fn other_entry_for(dir: &Path, exe: &Path) -> Option<PathBuf> {
+    let exe_name = exe.file_name()?.to_str()?;
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "desktop")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name != DESKTOP_ENTRY_NAME)
+        })
+        .find(|path| {
+            fs::read_to_string(path).is_ok_and(|content| {
+                content.lines().any(|line| {
+                    line.strip_prefix("Exec=")
+                        .is_some_and(|exec| exec.contains(exe_name))
+                })
+            })
+        })
+}
+
+pub fn enable_autostart() -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let dir = autostart_dir()?;
+    let path = dir.join(DESKTOP_ENTRY_NAME);
+    let entry = desktop_entry(&exe);
+    if fs::read_to_string(&path).is_ok_and(|content| content == entry) {
+        println!("Autostart is already enabled ({})", path.display());
+        return Ok(());
+    }
+    if let Some(other) = other_entry_for(&dir, &exe) {
+        println!("{} already starts warp-taskbar", other.display());
+        return Ok(());
+    }
+    fs::create_dir_all(&dir)?;
+    fs::write(&path, entry)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+pub fn disable_autostart() -> io::Result<()> {
+    let path = autostart_dir()?.join(DESKTOP_ENTRY_NAME);
+    if !path.exists() {
+        println!("{} does not exist", path.display());
+        return Ok(());
+    }
+    fs::remove_file(&path)?;
+    println!("Removed {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_start_escapes_the_path() {
+        assert_eq!(
+            exec_start(Path::new("/usr/bin/warp-taskbar")),
+            "/usr/bin/warp-taskbar"
+        );
+        assert_eq!(
+            exec_start(Path::new("/home/me/My Apps/warp-taskbar")),
+            "\"/home/me/My Apps/warp-taskbar\""
+        );
+        assert_eq!(
+            exec_start(Path::new("/opt/100%/$HOME/warp-taskbar")),
+            "/opt/100%%/$$HOME/warp-taskbar"
+        );
+    }
+}
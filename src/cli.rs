@@ -0,0 +1,91 @@
+use crate::config::{Config, MenuLayout, ThemeOverride};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+// Printed by --version, which clap handles before GTK is ever initialized
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("WARP_TASKBAR_GIT_HASH"),
+    ")"
+);
+
+// Settings are resolved in the order: command line flag, environment
+// variable, config file, compiled-in default. WARP_TASKBAR_ICON_THEME is the
+// exception and beats --theme too.
+#[derive(Debug, Parser)]
+#[command(version = VERSION, about = "Tray icon for Cloudflare WARP")]
+pub struct Cli {
+    /// Print a status block for i3blocks and exit
+    #[arg(long)]
+    pub i3blocks: bool,
+
+    /// Seconds between status polls
+    #[arg(long, value_name = "SECS")]
+    pub poll_secs: Option<u64>,
+
+    /// Icon theme, instead of detecting the desktop theme
+    #[arg(long, value_enum)]
+    pub theme: Option<ThemeOverride>,
+
+    /// Menu layout: every item at the top level, or grouped in submenus
+    #[arg(long, value_enum)]
+    pub menu_layout: Option<MenuLayout>,
+
+    /// Don't show connection change notifications
+    #[arg(long)]
+    pub no_notify: bool,
+
+    /// warp-cli binary to run
+    #[arg(long, value_name = "PATH")]
+    pub warp_cli_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// One-off tasks that run instead of the tray
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the connection status, mode and account, then exit. The exit
+    /// status is 1 if warp-cli can't report a status, including when
+    /// warp-svc isn't running.
+    Status {
+        /// Print a JSON object for scripts
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Write a systemd user unit that starts the tray with the session
+    #[cfg(target_os = "linux")]
+    InstallService,
+
+    /// Stop, disable and remove the systemd user unit
+    #[cfg(target_os = "linux")]
+    UninstallService,
+
+    /// Start the tray at login through ~/.config/autostart
+    #[cfg(target_os = "linux")]
+    EnableAutostart,
+
+    /// Remove the autostart entry
+    #[cfg(target_os = "linux")]
+    DisableAutostart,
+}
+
+impl Cli {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(secs) = self.poll_secs {
+            config.poll_secs = Some(secs);
+        }
+        if let Some(theme) = self.theme {
+            config.theme = theme;
+        }
+        if let Some(layout) = self.menu_layout {
+            config.menu.layout = layout;
+        }
+        if self.no_notify {
+            config.notifications = false;
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+// User settings read from $XDG_CONFIG_HOME/warp-taskbar/config.toml.
+// Every field is optional; missing ones keep the compiled-in defaults.
+//
+//     poll_secs = 5
+//     notifications = false
+//     theme = "dark"
+//     icon_style = "symbolic"
+//     warp_dir = "/var/lib/cloudflare-warp"
+//     icon_size = 48
+//     badge = false
+//     pause_minutes = 30
+//     restore_mode = true
+//     reapply_on_upgrade = true
+//     connect_after_mode_change = true
+//     start_daemon = true
+//     connectivity_probe = true
+//
+//     [[custom_command]]
+//     label = "Malware filtering"
+//     args = ["dns", "families", "malware"]
+//
+//     [redact]
+//     organization = false
+//     email = false
+//     ip_addresses = false
+//     device_name = false
+//
+//     [menu]
+//     layout = "flat"
+//     modes = false
+//     dns_families = false
+//     logging = false
+//     other = false
+//
+//     [icons]
+//     inactive = "/path/to/inactive.png"
+//     connecting = "/path/to/connecting.png"
+//     active = "/path/to/active.png"
+//     dark_active = "/path/to/dark-active.png"
+//     light_active = "/path/to/light-active.png"
+//     dir = "/path/to/icon-set"
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub poll_secs: Option<u64>,
+    pub notifications: bool,
+    pub theme: ThemeOverride,
+    pub icon_style: IconStyle,
+    // WARP's settings and log folder, opened from the menu
+    pub warp_dir: Option<PathBuf>,
+    // Pixel size to render icons at, for HiDPI panels; within ICON_SIZES
+    pub icon_size: Option<u32>,
+    // Mark the connected icon with the current mode
+    pub badge: bool,
+    // How long "Pause" keeps WARP disconnected; within PAUSE_MINUTES
+    pub pause_minutes: u64,
+    // Re-apply the mode last picked from the menu at startup, in case WARP
+    // came back in its default mode
+    pub restore_mode: bool,
+    // Re-apply the mode last picked from the menu once after warp-cli was
+    // upgraded, since an upgrade sometimes resets it
+    pub reapply_on_upgrade: bool,
+    // Connect a disconnected WARP once a mode picked from the menu is set
+    pub connect_after_mode_change: bool,
+    // Offer to start warp-svc from the menu when it isn't running. Off by
+    // default, as it asks for administrator rights.
+    pub start_daemon: bool,
+    // While connected, check every 30 seconds that 1.1.1.1 answers, over
+    // IPv6 too where there is a route, and say so once when it keeps failing
+    pub connectivity_probe: bool,
+    // Extra warp-cli invocations for the Custom submenu
+    #[serde(rename = "custom_command")]
+    pub custom_commands: Vec<CustomCommand>,
+    pub redact: Redact,
+    pub menu: MenuGroups,
+    pub icons: IconPaths,
+}
+
+// Icon sizes a panel could ask for. Anything else is a typo, and a huge one
+// would take a lot of memory to render.
+pub const ICON_SIZES: RangeInclusive<u32> = 16..=256;
+
+// A pause of up to a day
+pub const PAUSE_MINUTES: RangeInclusive<u64> = 1..=24 * 60;
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            poll_secs: None,
+            notifications: true,
+            theme: ThemeOverride::Auto,
+            icon_style: IconStyle::Auto,
+            warp_dir: None,
+            icon_size: None,
+            badge: true,
+            pause_minutes: 60,
+            restore_mode: false,
+            reapply_on_upgrade: false,
+            connect_after_mode_change: false,
+            start_daemon: false,
+            connectivity_probe: false,
+            custom_commands: Vec::new(),
+            redact: Redact::default(),
+            menu: MenuGroups::default(),
+            icons: IconPaths::default(),
+        }
+    }
+}
+
+// Forces the light or dark icon set instead of detecting the desktop theme
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeOverride {
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl ThemeOverride {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "auto" => Some(ThemeOverride::Auto),
+            "light" => Some(ThemeOverride::Light),
+            "dark" => Some(ThemeOverride::Dark),
+            _ => None,
+        }
+    }
+}
+
+// The bundled Cloudflare icons in color, or monochrome ones that fit a
+// symbolic icon theme. Auto picks symbolic on GNOME.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconStyle {
+    #[default]
+    Auto,
+    Color,
+    Symbolic,
+}
+
+// WARP_TASKBAR_ICON_THEME pins the icons for kiosk setups. Anything but auto
+// wins over theme and icon_style however they were set, --theme included,
+// and turns off theme detection. Symbolic icons are drawn for a dark panel,
+// like GNOME's top bar.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IconTheme {
+    #[default]
+    Auto,
+    AlwaysDarkIcons,
+    AlwaysLightIcons,
+    Symbolic,
+}
+
+impl IconTheme {
+    pub fn from_env() -> Self {
+        env::var("WARP_TASKBAR_ICON_THEME")
+            .ok()
+            .and_then(|value| IconTheme::parse(&value))
+            .unwrap_or_default()
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "auto" => Some(IconTheme::Auto),
+            "always-dark-icons" => Some(IconTheme::AlwaysDarkIcons),
+            "always-light-icons" => Some(IconTheme::AlwaysLightIcons),
+            "symbolic" => Some(IconTheme::Symbolic),
+            _ => None,
+        }
+    }
+}
+
+// `args` are passed to warp-cli as they are, the subcommand first
+#[derive(Clone, Debug, Deserialize)]
+pub struct CustomCommand {
+    pub label: String,
+    pub args: Vec<String>,
+}
+
+// What Copy Diagnostics replaces with "***", so the text can be pasted in
+// public. Each of these identifies the user and is left out unless turned off
+// here; account and device IDs are always left out.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Redact {
+    pub organization: bool,
+    pub email: bool,
+    // IPv4 and IPv6 addresses anywhere in the text, ranges included
+    pub ip_addresses: bool,
+    pub device_name: bool,
+}
+
+impl Default for Redact {
+    fn default() -> Self {
+        Redact {
+            organization: true,
+            email: true,
+            ip_addresses: true,
+            device_name: true,
+        }
+    }
+}
+
+// Groups of menu items that can be left out for a shorter menu
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MenuGroups {
+    pub layout: MenuLayout,
+    // The Set Mode items
+    pub modes: bool,
+    pub dns_families: bool,
+    // enable-logging, disable-logging and trace-support
+    pub logging: bool,
+    // teams-unenroll, register and generate-report
+    pub other: bool,
+}
+
+impl Default for MenuGroups {
+    fn default() -> Self {
+        MenuGroups {
+            layout: MenuLayout::Nested,
+            modes: true,
+            dns_families: true,
+            logging: true,
+            other: true,
+        }
+    }
+}
+
+// Every item at the top level, or related items (startup, modes, the
+// warp-cli extras) gathered in submenus
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuLayout {
+    Flat,
+    #[default]
+    Nested,
+}
+
+// `active` is used for both themes unless a themed variant is also given.
+// Besides bitmaps, icons may be SVGs; the active ones are then recolored to
+// contrast with the panel.
+//
+// `dir` holds a whole icon set named after the statuses: connected,
+// captive-portal, connecting, disconnected, unable-to-connect,
+// registration-missing, daemon-not-running and unknown, as .svg, .png or
+// .ico. A "-dark" or "-light" suffix, e.g. connected-dark.png, makes a
+// variant for that desktop theme. Icons from `dir` are used as they are, and
+// the ones it lacks come from above.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct IconPaths {
+    pub connecting: Option<PathBuf>,
+    pub inactive: Option<PathBuf>,
+    pub active: Option<PathBuf>,
+    pub dark_active: Option<PathBuf>,
+    pub light_active: Option<PathBuf>,
+    pub dir: Option<PathBuf>,
+}
+
+// $XDG_CONFIG_HOME, which defaults to ~/.config
+pub fn config_home() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    config_home().map(|dir| dir.join("warp-taskbar"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// A config file that exists but can't be used. The tray still starts, with
+// the defaults, so the user has to be told.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+pub fn load() -> (Config, Option<ConfigError>) {
+    let (mut config, error) = match read_file() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    config.apply_env();
+    (config, error)
+}
+
+// No config file at all is fine
+fn read_file() -> Result<Config, ConfigError> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => {
+            return Err(ConfigError {
+                path,
+                message: e.to_string(),
+            })
+        }
+    };
+    let config: Config = toml::from_str(&content).map_err(|e| ConfigError {
+        path: path.clone(),
+        message: e.to_string(),
+    })?;
+    config
+        .validate()
+        .map_err(|message| ConfigError { path, message })?;
+    Ok(config)
+}
+
+// Keeps the refresh rate picked from the menu for the next start. Only the
+// poll_secs line changes, so the rest of the file, comments included, stays
+// as the user wrote it.
+pub fn save_poll_secs(secs: u64) -> io::Result<()> {
+    let path = config_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(
+        &path,
+        set_top_level_key(&content, "poll_secs", &secs.to_string()),
+    )
+}
+
+// An existing line for the key is replaced where it is. A new one goes
+// first, since after a [table] header it would belong to that table.
+fn set_top_level_key(content: &str, key: &str, value: &str) -> String {
+    let line = format!("{} = {}", key, value);
+    let mut lines: Vec<&str> = content.lines().collect();
+    let top_level = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..top_level].iter().position(|line| {
+        line.split_once('=')
+            .is_some_and(|(name, _)| name.trim() == key)
+    });
+    match existing {
+        Some(index) => lines[index] = &line,
+        None => lines.insert(0, &line),
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+impl Config {
+    // Values that parse but can't be used
+    fn validate(&self) -> Result<(), String> {
+        if let Some(size) = self.icon_size.filter(|size| !ICON_SIZES.contains(size)) {
+            return Err(format!(
+                "icon_size {} is not between {} and {}",
+                size,
+                ICON_SIZES.start(),
+                ICON_SIZES.end()
+            ));
+        }
+        if !PAUSE_MINUTES.contains(&self.pause_minutes) {
+            return Err(format!(
+                "pause_minutes {} is not between {} and {}",
+                self.pause_minutes,
+                PAUSE_MINUTES.start(),
+                PAUSE_MINUTES.end()
+            ));
+        }
+        Ok(())
+    }
+
+    // Dark icons are the ones meant for a light theme, and the other way round
+    pub fn apply_icon_theme(&mut self, icon_theme: IconTheme) {
+        let (theme, icon_style) = match icon_theme {
+            IconTheme::Auto => return,
+            IconTheme::AlwaysDarkIcons => (ThemeOverride::Light, IconStyle::Color),
+            IconTheme::AlwaysLightIcons => (ThemeOverride::Dark, IconStyle::Color),
+            IconTheme::Symbolic => (ThemeOverride::Dark, IconStyle::Symbolic),
+        };
+        self.theme = theme;
+        self.icon_style = icon_style;
+    }
+
+    // WARP_TASKBAR_POLL_SECS and WARP_TASKBAR_THEME (light, dark or auto)
+    // take precedence over the config file
+    fn apply_env(&mut self) {
+        if let Some(secs) = env::var("WARP_TASKBAR_POLL_SECS")
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+        {
+            self.poll_secs = Some(secs);
+        }
+        if let Some(theme) = env::var("WARP_TASKBAR_THEME")
+            .ok()
+            .and_then(|value| ThemeOverride::parse(&value))
+        {
+            self.theme = theme;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_an_existing_key_in_place() {
+        let content = "# Poll less often\npoll_secs = 5\nbadge = false\n";
+        assert_eq!(
+            set_top_level_key(content, "poll_secs", "10"),
+            "# Poll less often\npoll_secs = 10\nbadge = false\n"
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_key_before_any_table() {
+        let content = "badge = false\n\n[icons]\npoll_secs = 3\n";
+        assert_eq!(
+            set_top_level_key(content, "poll_secs", "1"),
+            "poll_secs = 1\nbadge = false\n\n[icons]\npoll_secs = 3\n"
+        );
+        assert_eq!(set_top_level_key("", "poll_secs", "2"), "poll_secs = 2\n");
+    }
+
+    #[test]
+    fn icon_sizes_must_be_in_range() {
+        let config = |content| toml::from_str::<Config>(content).unwrap();
+        assert!(config("icon_size = 48").validate().is_ok());
+        assert!(config("icon_size = 0").validate().is_err());
+        assert!(config("icon_size = 100000").validate().is_err());
+    }
+
+    #[test]
+    fn pause_minutes_must_be_in_range() {
+        let config = |content| toml::from_str::<Config>(content).unwrap();
+        assert!(config("pause_minutes = 30").validate().is_ok());
+        assert!(config("pause_minutes = 0").validate().is_err());
+        assert!(config("pause_minutes = 9223372036854775807")
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::state::state_dir;
+use log::warn;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::process::Output;
+use std::time::SystemTime;
+
+// Every warp-cli command run from the tray is appended to
+// $XDG_STATE_HOME/warp-taskbar/commands.log. Once the log passes
+// MAX_LOG_BYTES it is moved to commands.log.1, replacing the previous one.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+// Output beyond this many characters is cut off
+const MAX_OUTPUT_CHARS: usize = 500;
+
+fn truncate(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+fn format_entry(command: &str, args: &[&str], result: &io::Result<Output>) -> String {
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+    let invocation = [command]
+        .iter()
+        .chain(args)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (outcome, text) = match result {
+        Ok(output) => (
+            output.status.to_string(),
+            format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        ),
+        Err(e) => ("failed to start".to_string(), e.to_string()),
+    };
+
+    let mut entry = format!("[{}] warp-cli {}: {}\n", timestamp, invocation, outcome);
+    let text = text.trim();
+    for line in truncate(text, MAX_OUTPUT_CHARS).lines() {
+        entry.push_str("    ");
+        entry.push_str(line);
+        entry.push('\n');
+    }
+    if text.chars().count() > MAX_OUTPUT_CHARS {
+        entry.push_str("    ...\n");
+    }
+    entry
+}
+
+fn append(entry: &str) -> io::Result<()> {
+    let Some(dir) = state_dir() else {
+        return Ok(());
+    };
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("commands.log");
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() > MAX_LOG_BYTES) {
+        fs::rename(&path, dir.join("commands.log.1"))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(entry.as_bytes())
+}
+
+pub fn record(command: &str, args: &[&str], result: &io::Result<Output>) {
+    if let Err(e) = append(&format_entry(command, args, result)) {
+        warn!("Failed to write command history: {}", e);
+    }
+}
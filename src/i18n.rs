@@ -0,0 +1,98 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use log::{debug, warn};
+use std::env;
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+// (locale, contents) of every i18n/<locale>.ftl, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/locales.rs"));
+
+// Complete translation that fills in whatever another locale is missing
+const DEFAULT_LOCALE: &str = "en";
+
+// The first of LC_ALL, LC_MESSAGES and LANG that is set, e.g. "de_DE.UTF-8"
+fn requested_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+// "de_DE.UTF-8" picks de-DE.ftl if there is one, otherwise de.ftl
+fn find_locale(requested: &str) -> Option<(&'static str, &'static str)> {
+    let tag = requested
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .replace('_', "-");
+    let language = tag.split('-').next().unwrap_or_default();
+    for wanted in [tag.as_str(), language] {
+        if let Some(found) = LOCALES
+            .iter()
+            .find(|(locale, _)| locale.eq_ignore_ascii_case(wanted))
+        {
+            return Some(*found);
+        }
+    }
+    None
+}
+
+fn add_locale(bundle: &mut FluentBundle<FluentResource>, locale: &str, source: &str) {
+    let resource = match FluentResource::try_new(source.to_string()) {
+        Ok(resource) => resource,
+        Err((resource, errors)) => {
+            warn!("Errors in {}.ftl: {:?}", locale, errors);
+            resource
+        }
+    };
+    bundle.add_resource_overriding(resource);
+}
+
+fn bundle() -> &'static FluentBundle<FluentResource> {
+    static BUNDLE: OnceLock<FluentBundle<FluentResource>> = OnceLock::new();
+    BUNDLE.get_or_init(|| {
+        let selected = requested_locale().and_then(|requested| find_locale(&requested));
+        debug!("Locale: {:?}", selected.map(|(locale, _)| locale));
+
+        let mut langids = Vec::new();
+        if let Some(langid) = selected.and_then(|(locale, _)| locale.parse().ok()) {
+            langids.push(langid);
+        }
+        langids.push(DEFAULT_LOCALE.parse::<LanguageIdentifier>().unwrap());
+
+        let mut bundle = FluentBundle::new_concurrent(langids);
+        // Unicode isolation marks around variables show up as boxes in some
+        // tray menus
+        bundle.set_use_isolating(false);
+        if let Some((_, source)) = LOCALES.iter().find(|(locale, _)| *locale == DEFAULT_LOCALE) {
+            add_locale(&mut bundle, DEFAULT_LOCALE, source);
+        }
+        if let Some((locale, source)) = selected.filter(|(locale, _)| *locale != DEFAULT_LOCALE) {
+            add_locale(&mut bundle, locale, source);
+        }
+        bundle
+    })
+}
+
+// Translated text of a message; the id itself if no locale has it
+pub fn tr(id: &str) -> String {
+    tr_args(id, &[])
+}
+
+pub fn tr_args(id: &str, args: &[(&str, &str)]) -> String {
+    let bundle = bundle();
+    let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+        warn!("Missing translation for {}", id);
+        return id.to_string();
+    };
+
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, *value);
+    }
+    let mut errors = Vec::new();
+    bundle
+        .format_pattern(pattern, Some(&fluent_args), &mut errors)
+        .into_owned()
+}
@@ -1,366 +1,4806 @@
-use gtk;
+#[cfg(target_os = "linux")]
+mod autostart;
+mod cli;
+mod config;
+mod history;
+mod i18n;
+mod overlay;
+mod runner;
+mod state;
+mod theme;
+
+use clap::Parser;
+use cli::Cli;
+use config::{Config, IconPaths, IconStyle, IconTheme, MenuLayout, Redact};
+#[cfg(all(unix, not(target_os = "macos")))]
+use gtk::prelude::*;
+use i18n::{tr, tr_args};
+use log::{debug, error, info, warn};
+use overlay::Badge;
+use runner::{CommandRunner, SystemRunner};
+use serde::Serialize;
+use std::borrow::Cow;
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::{process::Command, time::Duration};
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::{
+    process::{Child, Command, ExitCode, Output},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use theme::ThemeCache;
 use tray_icon::{
-    menu::{Menu, MenuEvent, MenuItem},
-    Icon, TrayIconBuilder,
+    menu::{
+        accelerator::{Accelerator, Code, Modifiers},
+        CheckMenuItem, IsMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu,
+    },
+    Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
 };
 
-// Include your icons
-static TRAY_ICON_DARK_ACTIVE: &[u8] = include_bytes!("../icon/cloudflare-dark-active.ico");
-static TRAY_ICON_INACTIVE: &[u8] = include_bytes!("../icon/cloudflare-inactive.ico");
-static TRAY_ICON_LIGHT_ACTIVE: &[u8] = include_bytes!("../icon/cloudflare-light-active.ico");
+const ACCOUNT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 
-pub fn is_dark_mode_enabled() -> bool {
-    // Check for GNOME
-    if let Ok(output) = Command::new("gsettings")
-        .args(["get", "org.gnome.desktop.interface", "color-scheme"])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("dark") {
-            return true;
+// Tunnel statistics keep changing while connected, so they are re-read on
+// their own timer rather than with each status read
+const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+// Settings (mode, Always-On) are re-read this often, or on the next tick
+// after the menu changed them
+const SETTINGS_REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+static SETTINGS_CHANGED: AtomicBool = AtomicBool::new(false);
+
+// Set by the Refresh item and after each command the user runs; the main
+// loop reads the status on its next pass instead of waiting for the poll
+// timer
+static REFRESH_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn request_refresh() {
+    REFRESH_REQUESTED.store(true, Ordering::SeqCst);
+    wake_main_loop();
+}
+
+// --warp-cli-path or WARP_CLI_PATH point at a warp-cli that isn't on the GUI
+// session's PATH
+static WARP_CLI_PATH: OnceLock<OsString> = OnceLock::new();
+
+fn warp_cli_path() -> &'static OsString {
+    WARP_CLI_PATH.get_or_init(|| env::var_os("WARP_CLI_PATH").unwrap_or_else(|| "warp-cli".into()))
+}
+
+// Inside a Flatpak sandbox the host's warp-cli can only be reached through
+// flatpak-spawn. WARP_TASKBAR_HOST_SPAWN=1 or 0 overrides the detection.
+static HOST_SPAWN: OnceLock<bool> = OnceLock::new();
+
+fn host_spawn() -> bool {
+    *HOST_SPAWN.get_or_init(|| match env::var("WARP_TASKBAR_HOST_SPAWN").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => cfg!(target_os = "linux") && Path::new("/.flatpak-info").exists(),
+    })
+}
+
+// Every program meant for the host, warp-cli first of all, goes through
+// here: the program to start and the arguments to give it, wrapped in
+// flatpak-spawn when needed
+fn host_command_line(program: &OsStr, args: &[&str]) -> (OsString, Vec<String>) {
+    let args = args.iter().map(|arg| arg.to_string());
+    if host_spawn() {
+        let host_args = ["--host".to_string(), program.to_string_lossy().into_owned()];
+        (
+            "flatpak-spawn".into(),
+            host_args.into_iter().chain(args).collect(),
+        )
+    } else {
+        (program.to_owned(), args.collect())
+    }
+}
+
+fn run_host_command(
+    runner: &dyn CommandRunner,
+    program: &OsStr,
+    args: &[&str],
+) -> io::Result<Output> {
+    let (program, args) = host_command_line(program, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    runner.run(&program, &args)
+}
+
+fn run_warp_cli(runner: &dyn CommandRunner, args: &[&str]) -> io::Result<Output> {
+    run_host_command(runner, warp_cli_path(), args)
+}
+
+// The same for a warp-cli that keeps running, with its stdout piped
+fn spawn_warp_cli(runner: &dyn CommandRunner, args: &[&str]) -> io::Result<Child> {
+    let (program, args) = host_command_line(warp_cli_path(), args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    runner.spawn(&program, &args)
+}
+
+// Modes offered by `warp-cli set-mode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WarpMode {
+    Warp,
+    Doh,
+    Dot,
+    WarpDoh,
+    WarpDot,
+    // A local SOCKS/HTTP proxy instead of a tunnel for all traffic
+    Proxy,
+}
+
+impl WarpMode {
+    const ALL: [WarpMode; 6] = [
+        WarpMode::Warp,
+        WarpMode::Doh,
+        WarpMode::Dot,
+        WarpMode::WarpDoh,
+        WarpMode::WarpDot,
+        WarpMode::Proxy,
+    ];
+
+    fn from_arg(arg: &str) -> Option<Self> {
+        WarpMode::ALL.into_iter().find(|mode| mode.arg() == arg)
+    }
+
+    // Argument for `warp-cli set-mode`
+    fn arg(self) -> &'static str {
+        match self {
+            WarpMode::Warp => "warp",
+            WarpMode::Doh => "doh",
+            WarpMode::Dot => "dot",
+            WarpMode::WarpDoh => "warp+doh",
+            WarpMode::WarpDot => "warp+dot",
+            WarpMode::Proxy => "proxy",
         }
     }
 
-    // Check for KDE Plasma
-    if let Some(home) = env::var_os("HOME") {
-        let kde_config_path = Path::new(&home).join(".config").join("kdeglobals");
-        if kde_config_path.exists() {
-            if let Ok(content) = fs::read_to_string(kde_config_path) {
-                if content.contains("[Colors:View]") && content.contains("BackgroundNormal=") {
-                    if content.contains("BackgroundNormal=35,38,41") {
-                        return true;
-                    }
-                }
-                if content.contains("ColorScheme=BreezeDark")
-                    || content.contains("name=Breeze Dark")
-                {
-                    return true;
+    // A dot for plain WARP, otherwise the DNS transport: D for DNS over
+    // HTTPS, T for DNS over TLS. P for proxy mode.
+    fn badge(self) -> Badge {
+        match self {
+            WarpMode::Warp => Badge::Dot,
+            WarpMode::Doh | WarpMode::WarpDoh => Badge::Letter('D'),
+            WarpMode::Dot | WarpMode::WarpDot => Badge::Letter('T'),
+            WarpMode::Proxy => Badge::Letter('P'),
+        }
+    }
+
+    // Inside the Set Mode submenu the mode alone says enough
+    fn menu_label(self, layout: MenuLayout) -> String {
+        match layout {
+            MenuLayout::Flat => tr_args("menu-set-mode", &[("mode", self.arg())]),
+            MenuLayout::Nested => self.arg().to_string(),
+        }
+    }
+
+    fn menu_id(self) -> &'static str {
+        match self {
+            WarpMode::Warp => "set_mode_warp",
+            WarpMode::Doh => "set_mode_doh",
+            WarpMode::Dot => "set_mode_dot",
+            WarpMode::WarpDoh => "set_mode_warp_doh",
+            WarpMode::WarpDot => "set_mode_warp_dot",
+            WarpMode::Proxy => "set_mode_proxy",
+        }
+    }
+}
+
+// Source and value of a "Key: value" line of warp-cli output. Newer versions
+// prefix settings lines with where they came from, e.g.
+// "(user set)\tMode: Warp".
+fn field<'a>(settings: &'a str, key: &str) -> Option<(Option<&'a str>, &'a str)> {
+    settings.lines().find_map(|line| {
+        let line = line.trim();
+        let (source, line) = match line.strip_prefix('(') {
+            Some(rest) => {
+                let (source, line) = rest.split_once(')')?;
+                (Some(source), line.trim_start())
+            }
+            None => (None, line),
+        };
+        Some((source, line.strip_prefix(key)?.strip_prefix(':')?.trim()))
+    })
+}
+
+fn field_value<'a>(settings: &'a str, key: &str) -> Option<&'a str> {
+    field(settings, key).map(|(_, value)| value)
+}
+
+// Settings from an MDM file ("local policy") or the organization's device
+// profile ("network policy") can't be changed with warp-cli
+fn is_managed(settings: &str, key: &str) -> bool {
+    field(settings, key).is_some_and(|(source, _)| source.is_some_and(|s| s.contains("policy")))
+}
+
+// Menu items for managed settings say so, as they are disabled
+fn managed_label(label: String, managed: bool) -> String {
+    if managed {
+        tr_args("menu-managed", &[("item", &label)])
+    } else {
+        label
+    }
+}
+
+fn parse_warp_mode(settings: &str) -> Option<WarpMode> {
+    let value = field_value(settings, "Mode")?;
+    let name: String = value
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '+')
+        .collect();
+    match name.to_lowercase().as_str() {
+        "warp" => Some(WarpMode::Warp),
+        "dnsoverhttps" | "doh" => Some(WarpMode::Doh),
+        "dnsovertls" | "dot" => Some(WarpMode::Dot),
+        "warpwithdnsoverhttps" | "warp+doh" => Some(WarpMode::WarpDoh),
+        "warpwithdnsovertls" | "warp+dot" => Some(WarpMode::WarpDot),
+        "warpproxy" | "proxy" => Some(WarpMode::Proxy),
+        _ => None,
+    }
+}
+
+fn parse_always_on(settings: &str) -> Option<bool> {
+    field_value(settings, "Always On")?.parse().ok()
+}
+
+// Menu ids of proxy port presets are this prefix plus the port
+const PROXY_PORT_ID_PREFIX: &str = "proxy_port:";
+
+// 40000 is WARP's own default
+const PROXY_PORTS: [u16; 3] = [1080, 8080, 40000];
+
+// In proxy mode the port follows the mode, e.g. "Mode: WarpProxy on port 40000"
+fn parse_proxy_port(settings: &str) -> Option<u16> {
+    let (_, port) = field_value(settings, "Mode")?.split_once("on port")?;
+    parse_port(port)
+}
+
+// Port 0 would leave the choice to the OS
+fn parse_port(value: &str) -> Option<u16> {
+    value.trim().parse::<u16>().ok().filter(|&port| port != 0)
+}
+
+fn current_mode_label(mode: Option<WarpMode>) -> String {
+    match mode {
+        Some(mode) => tr_args("menu-current-mode", &[("mode", mode.arg())]),
+        None => tr("menu-current-mode-unknown"),
+    }
+}
+
+fn proxy_port_label(port: Option<u16>) -> String {
+    match port {
+        Some(port) => tr_args("menu-proxy-port", &[("port", &port.to_string())]),
+        None => tr("menu-proxy-port-unknown"),
+    }
+}
+
+// "Team (my-org)" for Teams devices, otherwise the account type. None when the
+// device isn't registered.
+fn parse_account(stdout: &str) -> Option<String> {
+    let account_type = field_value(stdout, "Account type")?;
+    Some(match field_value(stdout, "Organization") {
+        Some(organization) => format!("{} ({})", account_type, organization),
+        None => account_type.to_string(),
+    })
+}
+
+// Both streams, since errors about the registration end up on stderr
+fn read_warp_account_output(runner: &dyn CommandRunner) -> Option<String> {
+    let output = run_warp_cli(runner, &["account"]).ok()?;
+    Some(format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+fn read_warp_account(runner: &dyn CommandRunner) -> Option<String> {
+    parse_account(&read_warp_account_output(runner)?)
+}
+
+// Wording warp-cli has used for a registration or license that ran out, or
+// a clock too far off for it to be checked, lowercased. Only its error
+// messages, so that fields like a key's expiry date or a device named after
+// a clock don't count; when nothing matches all is well.
+const EXPIRY_PHRASES: [&str; 8] = [
+    "registration has expired",
+    "registration expired",
+    "license has expired",
+    "license expired",
+    "invalid license",
+    "invalid registration",
+    "clock skew",
+    "clock is out of sync",
+];
+
+// The line explaining the problem, to show as it is
+fn parse_expiry_warning(output: &str) -> Option<&str> {
+    output.lines().map(str::trim).find(|line| {
+        let line = line.to_lowercase();
+        EXPIRY_PHRASES.iter().any(|phrase| line.contains(phrase))
+    })
+}
+
+// Menu ids of virtual network items are this prefix plus the vnet id
+const VNET_ID_PREFIX: &str = "vnet:";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct VirtualNetwork {
+    id: String,
+    name: String,
+}
+
+// `warp-cli vnet` lists each network as an "ID:" line followed by its details,
+// plus a "Currently selected:" line with the active id
+fn parse_vnets(stdout: &str) -> (Vec<VirtualNetwork>, Option<String>) {
+    let mut vnets: Vec<VirtualNetwork> = Vec::new();
+    let mut selected = None;
+    for line in stdout.lines() {
+        let Some((key, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "ID" => vnets.push(VirtualNetwork {
+                id: value.to_string(),
+                name: value.to_string(),
+            }),
+            "Name" => {
+                if let Some(vnet) = vnets.last_mut() {
+                    vnet.name = value.to_string();
                 }
             }
+            "Currently selected" => selected = Some(value.to_string()),
+            _ => {}
         }
     }
+    (vnets, selected)
+}
 
-    // Check for XFCE
-    if let Ok(output) = Command::new("xfconf-query")
-        .args(["-c", "xsettings", "-p", "/Net/ThemeName"])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("dark") || stdout.contains("Dark") {
-            return true;
-        }
+fn read_warp_vnets(runner: &dyn CommandRunner) -> (Vec<VirtualNetwork>, Option<String>) {
+    match run_warp_cli(runner, &["vnet"]) {
+        Ok(output) => parse_vnets(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => (Vec::new(), None),
     }
+}
 
-    // Check for Cinnamon
-    if let Ok(output) = Command::new("gsettings")
-        .args(["get", "org.cinnamon.desktop.interface", "gtk-theme"])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("dark") || stdout.contains("Dark") {
-            return true;
+// Menu ids of DNS family items are this prefix plus the family name
+const DNS_FAMILY_ID_PREFIX: &str = "dns_family:";
+
+// Menu ids of the config's custom commands end in their position in the list
+const CUSTOM_COMMAND_ID_PREFIX: &str = "custom_command:";
+
+// The families accepted by `warp-cli dns families <MODE>` are listed in its
+// help, either in clap's long form ("- malware: Block malware" lines after
+// "Possible values:") or its short form ("[possible values: off, malware]")
+fn parse_dns_families(help: &str) -> Vec<String> {
+    if let Some(start) = help.find("[possible values:") {
+        let list = &help[start + "[possible values:".len()..];
+        let list = list.split(']').next().unwrap_or_default();
+        return list
+            .split(',')
+            .map(|family| family.trim().to_string())
+            .filter(|family| !family.is_empty())
+            .collect();
+    }
+
+    let mut families = Vec::new();
+    let mut in_values = false;
+    for line in help.lines() {
+        let line = line.trim();
+        if line == "Possible values:" {
+            in_values = true;
+            continue;
+        }
+        if !in_values {
+            continue;
+        }
+        let Some(entry) = line.strip_prefix("- ") else {
+            break;
+        };
+        let family = entry.split(':').next().unwrap_or_default().trim();
+        if !family.is_empty() {
+            families.push(family.to_string());
         }
     }
+    families
+}
 
-    // Check for MATE
-    if let Ok(output) = Command::new("gsettings")
-        .args(["get", "org.mate.interface", "gtk-theme"])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("dark") || stdout.contains("Dark") {
-            return true;
+// Older warp-cli versions have no `dns` subcommand and end up with no families
+fn read_dns_families(runner: &dyn CommandRunner) -> Vec<String> {
+    match run_warp_cli(runner, &["dns", "families", "--help"]) {
+        Ok(output) if output.status.success() => {
+            parse_dns_families(&String::from_utf8_lossy(&output.stdout))
         }
+        _ => Vec::new(),
     }
+}
 
-    // Check for Elementary OS
-    if let Ok(output) = Command::new("gsettings")
-        .args([
-            "get",
-            "io.elementary.terminal.settings",
-            "prefer-dark-style",
-        ])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("true") {
-            return true;
+// The custom DNS endpoint is a Gateway DoH subdomain, which the settings show
+// as a hostname under GATEWAY_DOH_DOMAIN
+const GATEWAY_DOH_DOMAIN: &str = ".cloudflare-gateway.com";
+
+fn parse_dns_endpoint(settings: &str) -> Option<&str> {
+    field_value(settings, "Gateway DoH Subdomain")
+        .or_else(|| field_value(settings, "Gateway ID"))
+        .filter(|value| !value.is_empty())
+}
+
+// Accepts the subdomain id on its own, as a hostname or as the DoH URL the
+// dashboard shows, and returns the id `warp-cli set-gateway` expects
+fn parse_gateway_id(input: &str) -> Option<String> {
+    let input = input.trim();
+    let host = input.strip_prefix("https://").unwrap_or(input);
+    let host = host.split('/').next().unwrap_or_default();
+    let id = host.strip_suffix(GATEWAY_DOH_DOMAIN).unwrap_or(host);
+    let valid = !id.is_empty()
+        && id.len() <= 63
+        && !id.starts_with('-')
+        && !id.ends_with('-')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then(|| id.to_ascii_lowercase())
+}
+
+fn dns_endpoint_label(endpoint: Option<&str>) -> String {
+    match endpoint {
+        Some(endpoint) => tr_args("menu-dns-endpoint", &[("endpoint", endpoint)]),
+        None => tr("menu-dns-endpoint-default"),
+    }
+}
+
+// Asks for the endpoint; an invalid one is reported rather than passed on
+fn set_dns_endpoint(runner: &dyn CommandRunner) {
+    let current = read_warp_settings(runner)
+        .as_deref()
+        .and_then(parse_dns_endpoint)
+        .unwrap_or_default()
+        .to_string();
+    let Some(input) = ask_text(tr("dialog-dns-endpoint"), current) else {
+        return;
+    };
+    match parse_gateway_id(&input) {
+        Some(id) => {
+            let _ = run_warp_command(runner, "set-gateway", &[&id]);
+            SETTINGS_CHANGED.store(true, Ordering::SeqCst);
         }
+        None => notify(
+            &tr("notify-invalid-dns-endpoint"),
+            &tr_args(
+                "notify-invalid-dns-endpoint-body",
+                &[("input", input.trim())],
+            ),
+        ),
     }
+}
 
-    // Fallback: check GTK theme setting in general
-    if let Ok(output) = Command::new("gsettings")
-        .args(["get", "org.gnome.desktop.interface", "gtk-theme"])
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("dark") || stdout.contains("Dark") {
-            return true;
+// Each "Key: value" pair of `warp-cli tunnel stats` becomes a label. Some
+// lines hold several pairs separated by ';'. Anything else is skipped so a
+// format change can't break the menu.
+fn parse_tunnel_stats(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .flat_map(|line| line.split(';'))
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once(':')?;
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() || value.is_empty() {
+                return None;
+            }
+            Some(format!("{}: {}", key, value))
+        })
+        .collect()
+}
+
+// Keys newer warp-cli versions give the Cloudflare data center under
+const COLO_KEYS: [&str; 4] = ["colo", "colocation", "data center", "datacenter"];
+
+// The data center's code (e.g. SIN) from the tunnel stats labels. None when
+// this warp-cli doesn't report one.
+fn parse_colo(stats: &[String]) -> Option<&str> {
+    stats.iter().find_map(|label| {
+        let (key, value) = label.split_once(':')?;
+        let key = key.trim().to_lowercase();
+        COLO_KEYS
+            .contains(&key.as_str())
+            .then(|| value.split_whitespace().next())
+            .flatten()
+    })
+}
+
+fn read_tunnel_stats(runner: &dyn CommandRunner) -> Vec<String> {
+    match run_warp_cli(runner, &["tunnel", "stats"]) {
+        Ok(output) if output.status.success() => {
+            parse_tunnel_stats(&String::from_utf8_lossy(&output.stdout))
         }
+        _ => Vec::new(),
     }
+}
 
-    false
+fn read_warp_settings(runner: &dyn CommandRunner) -> Option<String> {
+    let output = run_warp_cli(runner, &["settings"]).ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
-fn get_active_tray_icon() -> &'static [u8] {
-    if is_dark_mode_enabled() {
-        TRAY_ICON_LIGHT_ACTIVE
-    } else {
-        TRAY_ICON_DARK_ACTIVE
+// Year, month and build of a warp-cli release, e.g. 2024.6.415
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct WarpCliVersion(u32, u32, u32);
+
+impl fmt::Display for WarpCliVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
     }
 }
 
-fn is_warp_disconnected() -> bool {
-    let output = Command::new("warp-cli")
-        .arg("status")
-        .output()
-        .expect("Failed to execute warp-cli status command");
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    stdout.contains("Status update: Disconnected")
+// First releases with the subcommands behind the optional menus
+const VNET_MIN_VERSION: WarpCliVersion = WarpCliVersion(2023, 3, 0);
+const DNS_FAMILIES_MIN_VERSION: WarpCliVersion = WarpCliVersion(2024, 2, 0);
+
+// `warp-cli --version` prints e.g. "warp-cli 2024.6.415.0"
+fn parse_warp_cli_version(stdout: &str) -> Option<WarpCliVersion> {
+    let version = stdout
+        .split_whitespace()
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(WarpCliVersion(major, minor, patch))
 }
 
-struct AppIcons {
-    cloudflare_dark_active: &'static [u8],
-    cloudflare_inactive: &'static [u8],
-    cloudflare_light_active: &'static [u8],
+// What the installed warp-cli can do, decided once at startup
+struct WarpCliInfo {
+    version: Option<WarpCliVersion>,
 }
 
-const APP_ICONS: AppIcons = AppIcons {
-    cloudflare_dark_active: include_bytes!("../icon/cloudflare-dark-active.ico"),
-    cloudflare_inactive: include_bytes!("../icon/cloudflare-inactive.ico"),
-    cloudflare_light_active: include_bytes!("../icon/cloudflare-light-active.ico"),
-};
+impl WarpCliInfo {
+    // An unrecognized version is assumed to be new enough
+    fn supports(&self, min_version: WarpCliVersion) -> bool {
+        self.version.is_none_or(|version| version >= min_version)
+    }
+}
+
+// Startup check so a missing warp-cli is reported instead of polled forever.
+// Only the first call runs warp-cli; later ones (About) reuse its answer.
+fn detect_warp_cli(runner: &dyn CommandRunner) -> Option<&'static WarpCliInfo> {
+    static DETECTED: OnceLock<Option<WarpCliInfo>> = OnceLock::new();
+    DETECTED
+        .get_or_init(|| {
+            let output = run_warp_cli(runner, &["--version"]).ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            let version = parse_warp_cli_version(&String::from_utf8_lossy(&output.stdout));
+            debug!("warp-cli version: {:?}", version);
+            Some(WarpCliInfo { version })
+        })
+        .as_ref()
+}
 
-fn load_tray_icon(image_data: &[u8]) -> Icon {
-    let image = image::load_from_memory(image_data).expect("Failed to load icon image data");
-    let image_buffer = image.to_rgba8();
-    let pixels = image_buffer.into_flat_samples().samples;
-    Icon::from_rgba(pixels, image.width(), image.height()).expect("Failed to create tray icon")
+// Connection state as reported by `warp-cli status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WarpStatus {
+    Connected,
+    // Connected, but the network wants a sign-in page done first
+    CaptivePortal,
+    Connecting,
+    Disconnected,
+    UnableToConnect,
+    // Connecting fails until `warp-cli register` has been run
+    RegistrationMissing,
+    // warp-cli runs but can't reach warp-svc
+    DaemonNotRunning,
+    Unknown,
 }
 
-fn main() {
-    // Initialize GTK (needed on Linux/macOS)
-    if gtk::init().is_err() {
-        eprintln!("Failed to initialize GTK.");
-        return;
+impl WarpStatus {
+    fn label(self) -> String {
+        tr(match self {
+            WarpStatus::Connected => "status-connected",
+            WarpStatus::CaptivePortal => "status-captive-portal",
+            WarpStatus::Connecting => "status-connecting",
+            WarpStatus::Disconnected => "status-disconnected",
+            WarpStatus::UnableToConnect => "status-unable-to-connect",
+            WarpStatus::RegistrationMissing => "status-registration-missing",
+            WarpStatus::DaemonNotRunning => "status-daemon-not-running",
+            WarpStatus::Unknown => "status-unknown",
+        })
     }
 
-    // Create a flat tray menu (no nested submenus)
-    let tray_menu = Menu::new();
-    let connect_item = MenuItem::with_id("connect", "Warp Connect", true, None);
-    let disconnect_item = MenuItem::with_id("disconnect", "Warp Disconnect", true, None);
-    let status_item = MenuItem::with_id("status", "Warp Status", true, None);
+    fn is_connected(self) -> bool {
+        matches!(self, WarpStatus::Connected | WarpStatus::CaptivePortal)
+    }
 
-    // Instead of a submenu for startup options, we prefix the labels
-    let enable_always_on_item = MenuItem::with_id(
-        "enable_always_on",
-        "On StartUp: warp-cli enable-always-on",
-        true,
-        None,
-    );
-    let disable_always_on_item = MenuItem::with_id(
-        "disable_always_on",
-        "On StartUp: warp-cli disable-always-on",
-        true,
-        None,
-    );
+    // No status to be had: warp-cli can't run or has no warp-svc to ask
+    fn is_unreadable(self) -> bool {
+        matches!(self, WarpStatus::Unknown | WarpStatus::DaemonNotRunning)
+    }
+}
 
-    // Flatten set mode options
-    let set_mode_warp_item = MenuItem::with_id("set_mode_warp", "Set Mode: warp", true, None);
-    let set_mode_doh_item = MenuItem::with_id("set_mode_doh", "Set Mode: doh", true, None);
-    let set_mode_dot_item = MenuItem::with_id("set_mode_dot", "Set Mode: dot", true, None);
-    let set_mode_warp_doh_item =
-        MenuItem::with_id("set_mode_warp_doh", "Set Mode: warp+doh", true, None);
-    let set_mode_warp_dot_item =
-        MenuItem::with_id("set_mode_warp_dot", "Set Mode: warp+dot", true, None);
-
-    // Flatten "Other" options
-    let teams_unenroll_item = MenuItem::with_id(
-        "teams_unenroll",
-        "Other: warp-cli teams-unenroll",
-        true,
-        None,
-    );
-    let register_item = MenuItem::with_id("register", "Other: warp-cli register", true, None);
-    let enable_logging_item = MenuItem::with_id(
-        "enable_logging",
-        "Other: warp-cli enable-logging",
-        true,
-        None,
-    );
-    let disable_logging_item = MenuItem::with_id(
-        "disable_logging",
-        "Other: warp-cli disable-logging",
-        true,
-        None,
-    );
-    let trace_support_item =
-        MenuItem::with_id("trace_support", "Other: warp-cli trace-support", true, None);
-    let generate_report_item = MenuItem::with_id(
-        "generate_report",
-        "Other: warp-cli generate-report",
-        true,
-        None,
-    );
+fn parse_warp_status(stdout: &str) -> WarpStatus {
+    // Given as the reason for Unable, on a line of its own
+    if stdout
+        .lines()
+        .any(|line| line.contains("Registration Missing"))
+    {
+        return WarpStatus::RegistrationMissing;
+    }
 
-    // Append all items to the tray menu
-    tray_menu.append(&connect_item).unwrap();
-    tray_menu.append(&disconnect_item).unwrap();
-    tray_menu.append(&status_item).unwrap();
-    tray_menu.append(&enable_always_on_item).unwrap();
-    tray_menu.append(&disable_always_on_item).unwrap();
-    tray_menu.append(&set_mode_warp_item).unwrap();
-    tray_menu.append(&set_mode_doh_item).unwrap();
-    tray_menu.append(&set_mode_dot_item).unwrap();
-    tray_menu.append(&set_mode_warp_doh_item).unwrap();
-    tray_menu.append(&set_mode_warp_dot_item).unwrap();
-    tray_menu.append(&teams_unenroll_item).unwrap();
-    tray_menu.append(&register_item).unwrap();
-    tray_menu.append(&enable_logging_item).unwrap();
-    tray_menu.append(&disable_logging_item).unwrap();
-    tray_menu.append(&trace_support_item).unwrap();
-    tray_menu.append(&generate_report_item).unwrap();
-
-    // Build the tray icon with the menu and initial icon.
-    let tray_icon = TrayIconBuilder::new()
-        .with_menu(Box::new(tray_menu))
-        .with_tooltip("warp-cli wrapper")
-        .with_icon(load_tray_icon(APP_ICONS.cloudflare_inactive))
-        .build()
-        .expect("Failed to build tray icon");
-
-    // Clone the tray icon for use in our periodic update thread.
-    let tray_icon_ptr = tray_icon.clone();
-
-    // Spawn a thread to listen for menu events.
-    std::thread::spawn(|| loop {
-        match MenuEvent::receiver().recv() {
-            Ok(event) => match event.id.0.as_str() {
-                "connect" => {
-                    println!("Executing: warp-cli connect");
-                    if let Ok(output) = Command::new("warp-cli").arg("connect").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "disconnect" => {
-                    println!("Executing: warp-cli disconnect");
-                    if let Ok(output) = Command::new("warp-cli").arg("disconnect").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "status" => {
-                    println!("Executing: warp-cli status");
-                    if let Ok(output) = Command::new("warp-cli").arg("status").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "enable_always_on" => {
-                    println!("Executing: warp-cli enable-always-on");
-                    if let Ok(output) = Command::new("warp-cli").arg("enable-always-on").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "disable_always_on" => {
-                    println!("Executing: warp-cli disable-always-on");
-                    if let Ok(output) = Command::new("warp-cli").arg("disable-always-on").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "set_mode_warp" => {
-                    println!("Executing: warp-cli set-mode warp");
-                    if let Ok(output) = Command::new("warp-cli").args(["set-mode", "warp"]).output()
-                    {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "set_mode_doh" => {
-                    println!("Executing: warp-cli set-mode doh");
-                    if let Ok(output) = Command::new("warp-cli").args(["set-mode", "doh"]).output()
-                    {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "set_mode_dot" => {
-                    println!("Executing: warp-cli set-mode dot");
-                    if let Ok(output) = Command::new("warp-cli").args(["set-mode", "dot"]).output()
-                    {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "set_mode_warp_doh" => {
-                    println!("Executing: warp-cli set-mode warp+doh");
-                    if let Ok(output) = Command::new("warp-cli")
-                        .args(["set-mode", "warp+doh"])
-                        .output()
-                    {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "set_mode_warp_dot" => {
-                    println!("Executing: warp-cli set-mode warp+dot");
-                    if let Ok(output) = Command::new("warp-cli")
-                        .args(["set-mode", "warp+dot"])
-                        .output()
-                    {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "teams_unenroll" => {
-                    println!("Executing: warp-cli teams-unenroll");
-                    if let Ok(output) = Command::new("warp-cli").arg("teams-unenroll").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "register" => {
-                    println!("Executing: warp-cli register");
-                    if let Ok(output) = Command::new("warp-cli").arg("register").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "enable_logging" => {
-                    println!("Executing: warp-cli enable-logging");
-                    if let Ok(output) = Command::new("warp-cli").arg("enable-logging").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "disable_logging" => {
-                    println!("Executing: warp-cli disable-logging");
-                    if let Ok(output) = Command::new("warp-cli").arg("disable-logging").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
-                "trace_support" => {
-                    println!("Executing: warp-cli trace-support");
-                    if let Ok(output) = Command::new("warp-cli").arg("trace-support").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
+    let Some(status) = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Status update:"))
+    else {
+        return WarpStatus::Unknown;
+    };
+
+    let status = status.trim();
+    // Best effort: the portal is only mentioned in a reason or network line,
+    // and plain Connected is what it was before anyway
+    if status.starts_with("Connected") {
+        if stdout
+            .lines()
+            .any(|line| line.to_lowercase().contains("captive portal"))
+        {
+            WarpStatus::CaptivePortal
+        } else {
+            WarpStatus::Connected
+        }
+    } else if status.starts_with("Connecting") {
+        WarpStatus::Connecting
+    } else if status.starts_with("Disconnected") {
+        WarpStatus::Disconnected
+    } else if status.starts_with("Unable") {
+        WarpStatus::UnableToConnect
+    } else {
+        WarpStatus::Unknown
+    }
+}
+
+// What warp-cli prints on stderr when warp-svc isn't there to answer,
+// lowercased
+const DAEMON_DOWN_PHRASES: [&str; 3] = [
+    "unable to connect to the cloudflarewarp daemon",
+    "daemon is not running",
+    "connection refused",
+];
+
+fn daemon_not_running(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    DAEMON_DOWN_PHRASES
+        .iter()
+        .any(|phrase| stderr.contains(phrase))
+}
+
+// A status that can't be read is tried again after a short delay, since
+// warp-cli fails for a moment while warp-svc restarts. Once warp-cli says
+// warp-svc is down there is nothing to wait for.
+const STATUS_ATTEMPTS: u32 = 2;
+const STATUS_RETRY_DELAY: Duration = Duration::from_millis(300);
+
+// Unknown when warp-cli can't be run at all (e.g. not installed)
+fn read_warp_status(runner: &dyn CommandRunner) -> WarpStatus {
+    let mut status = WarpStatus::Unknown;
+    for attempt in 1..=STATUS_ATTEMPTS {
+        match run_warp_cli(runner, &["status"]) {
+            Ok(output) => {
+                status = parse_warp_status(&String::from_utf8_lossy(&output.stdout));
+                if status == WarpStatus::Unknown
+                    && daemon_not_running(&String::from_utf8_lossy(&output.stderr))
+                {
+                    status = WarpStatus::DaemonNotRunning;
                 }
-                "generate_report" => {
-                    println!("Executing: warp-cli generate-report");
-                    if let Ok(output) = Command::new("warp-cli").arg("generate-report").output() {
-                        println!("Output:\n{}", String::from_utf8_lossy(&output.stdout));
-                    }
+                debug!("warp-cli status: {:?} ({})", status, output.status);
+                if status != WarpStatus::Unknown {
+                    return status;
                 }
-                _ => {}
-            },
-            Err(e) => eprintln!("Error receiving menu event: {}", e),
+            }
+            Err(e) => warn!("Error running warp-cli status: {}", e),
         }
-    });
+        if attempt < STATUS_ATTEMPTS {
+            std::thread::sleep(STATUS_RETRY_DELAY);
+        }
+    }
+    status
+}
+
+// Starting warp-svc needs administrator rights, which each of these asks for
+#[cfg(all(unix, not(target_os = "macos")))]
+const START_DAEMON_COMMAND: &[&str] = &["pkexec", "systemctl", "start", "warp-svc"];
+#[cfg(windows)]
+const START_DAEMON_COMMAND: &[&str] = &[
+    "powershell",
+    "-NoProfile",
+    "-Command",
+    "Start-Process sc.exe -ArgumentList 'start','CloudflareWARP' -Verb RunAs -Wait",
+];
+#[cfg(target_os = "macos")]
+const START_DAEMON_COMMAND: &[&str] = &[
+    "osascript",
+    "-e",
+    "do shell script \"launchctl kickstart system/com.cloudflare.1dot1dot1dot1.macos.warp.daemon\" with administrator privileges",
+];
+
+fn start_daemon(runner: &dyn CommandRunner) {
+    if !confirm(tr("dialog-start-daemon")) {
+        return;
+    }
+    if let Err(e) = run_start_daemon_command(runner) {
+        notify(&tr("notify-start-daemon-failed"), &e);
+    }
+    request_refresh();
+}
+
+// On the host, like warp-cli, since that is where the service runs
+fn run_start_daemon_command(runner: &dyn CommandRunner) -> Result<(), String> {
+    info!("Executing: {}", START_DAEMON_COMMAND.join(" "));
+    let (program, args) = START_DAEMON_COMMAND.split_first().unwrap();
+    match run_host_command(runner, OsStr::new(program), args) {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// While the status listener runs, the rest of the menu (account, settings,
+// stats) is only re-read this often
+const LISTEN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// A listener that stopped is started again this long after the last start,
+// so a warp-svc restart doesn't leave the tray polling for good
+const LISTENER_RESTART_INTERVAL: Duration = Duration::from_secs(60);
+
+// `warp-cli --listen status` keeps running and prints a "Status update:"
+// line whenever the daemon's state changes. A warp-cli without --listen exits
+// right away, which leaves the poll timer in charge.
+struct StatusListener {
+    child: Child,
+    receiver: mpsc::Receiver<WarpStatus>,
+    alive: Arc<AtomicBool>,
+    started_at: Instant,
+}
+
+impl StatusListener {
+    fn spawn(runner: &dyn CommandRunner) -> Option<Self> {
+        let mut child = spawn_warp_cli(runner, &["--listen", "status"]).ok()?;
+        let stdout = child.stdout.take()?;
+        let (sender, receiver) = mpsc::channel();
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let (line_sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line_sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let reader_alive = alive.clone();
+        std::thread::spawn(move || {
+            forward_statuses(lines, sender);
+            reader_alive.store(false, Ordering::SeqCst);
+            info!("warp-cli status listener stopped, polling instead");
+        });
+        Some(StatusListener {
+            child,
+            receiver,
+            alive,
+            started_at: Instant::now(),
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    // Only the newest of several queued updates matters
+    fn latest(&self) -> Option<WarpStatus> {
+        self.receiver.try_iter().last()
+    }
+}
 
-    // Set up a GLib timeout to update the tray icon every 2 seconds.
-    glib::timeout_add_local(Duration::from_secs(2), move || {
-        if is_warp_disconnected() {
-            tray_icon_ptr.set_icon(Some(load_tray_icon(TRAY_ICON_INACTIVE)));
+// How long a status update waits for the line after it, which can turn
+// Connected into a captive portal
+const STATUS_UPDATE_SETTLE: Duration = Duration::from_millis(200);
+
+// Each status update is followed by lines about it, like the reason or the
+// captive portal. An update is read together with the lines after it, the
+// way a one-off read sees them, and only sent once the first of those
+// arrived, the next update started or none came for a moment, so a portal
+// doesn't show up as Connected first. A later line that changes the status
+// is sent too.
+fn forward_statuses(lines: mpsc::Receiver<String>, sender: mpsc::Sender<WarpStatus>) {
+    let mut update = String::new();
+    let mut sent = WarpStatus::Unknown;
+    let mut settling = false;
+    let send = |update: &str, sent: &mut WarpStatus| {
+        let status = parse_warp_status(update);
+        if status == WarpStatus::Unknown || status == *sent {
+            return true;
+        }
+        *sent = status;
+        let delivered = sender.send(status).is_ok();
+        wake_main_loop();
+        delivered
+    };
+    loop {
+        let line = if settling {
+            match lines.recv_timeout(STATUS_UPDATE_SETTLE) {
+                Ok(line) => Some(line),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
         } else {
-            tray_icon_ptr.set_icon(Some(load_tray_icon(get_active_tray_icon())));
+            match lines.recv() {
+                Ok(line) => Some(line),
+                Err(_) => break,
+            }
+        };
+        let delivered = match line {
+            None => {
+                settling = false;
+                send(&update, &mut sent)
+            }
+            Some(line) if line.trim().starts_with("Status update:") => {
+                let delivered = !settling || send(&update, &mut sent);
+                update = line + "\n";
+                sent = WarpStatus::Unknown;
+                settling = true;
+                delivered
+            }
+            Some(line) => {
+                update.push_str(&line);
+                update.push('\n');
+                settling = false;
+                send(&update, &mut sent)
+            }
+        };
+        if !delivered {
+            return;
         }
-        glib::ControlFlow::Continue
-    });
+    }
+    if settling {
+        send(&update, &mut sent);
+    }
+}
 
-    // Start the GTK main loop.
-    gtk::main();
+impl Drop for StatusListener {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A finished read. Passive ones come from the poll timer; the others were
+// asked for (Refresh, or a command the user just ran) and are shown as read.
+struct PolledStatus {
+    status: WarpStatus,
+    passive: bool,
+}
+
+// The main loop's warp-cli reads run on a thread of their own, since
+// warp-cli can hang and a failed status read waits before it retries. One
+// read of each kind runs at a time, and its result comes back through a
+// channel, like the listener's.
+struct BackgroundRead<T> {
+    sender: mpsc::Sender<T>,
+    receiver: mpsc::Receiver<T>,
+    busy: Arc<AtomicBool>,
+}
+
+impl<T: Send + 'static> BackgroundRead<T> {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        BackgroundRead {
+            sender,
+            receiver,
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::SeqCst)
+    }
+
+    // Does nothing while the last read is still running
+    fn start(&self, read: impl FnOnce() -> T + Send + 'static) {
+        if self.busy.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let sender = self.sender.clone();
+        let busy = self.busy.clone();
+        // No longer busy by the time the main loop wakes up for the result,
+        // so a Refresh waiting on this read can start its own
+        std::thread::spawn(move || {
+            let value = read();
+            busy.store(false, Ordering::SeqCst);
+            let _ = sender.send(value);
+            wake_main_loop();
+        });
+    }
+
+    fn latest(&self) -> Option<T> {
+        self.receiver.try_iter().last()
+    }
+}
+
+// Where notifications go: the desktop, unless another notifier was set
+// before the first one. Tests run the real menu handlers, and their
+// notifications have no place on the desktop.
+static NOTIFIER: OnceLock<fn(&str, &str)> = OnceLock::new();
+
+fn notify(summary: &str, body: &str) {
+    NOTIFIER.get_or_init(|| desktop_notify)(summary, body)
+}
+
+// Show a desktop notification; failures (no notification daemon) are ignored
+#[cfg(all(unix, not(target_os = "macos")))]
+fn desktop_notify(summary: &str, body: &str) {
+    let _ = Command::new("notify-send")
+        .args(["--app-name", "warp-taskbar", summary, body])
+        .status();
+}
+
+// Commands whose output is the point get a window with the full text
+const FULL_OUTPUT_COMMANDS: [&str; 2] = ["status", "trace-support"];
+
+// Commands that can cut off connectivity only run after the user confirms
+const CONFIRM_COMMANDS: [&str; 3] = ["teams-unenroll", "register", "disable-always-on"];
+
+// Run a warp-cli command from the menu and tell the user how it went. The
+// output is handed back too; a command the user declined to confirm is an
+// Interrupted error.
+fn run_warp_command(
+    runner: &dyn CommandRunner,
+    command: &str,
+    args: &[&str],
+) -> io::Result<Output> {
+    if CONFIRM_COMMANDS.contains(&command)
+        && !confirm(tr_args("dialog-confirm-command", &[("command", command)]))
+    {
+        info!("Cancelled warp-cli {}", command);
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!("warp-cli {} cancelled", command),
+        ));
+    }
+    info!("Executing: warp-cli {} {}", command, args.join(" "));
+    let invocation: Vec<&str> = [command].iter().chain(args).copied().collect();
+    let result = run_warp_cli(runner, &invocation);
+    history::record(command, args, &result);
+    request_refresh();
+    match &result {
+        Ok(output) => {
+            info!("warp-cli {} exited with {}", command, output.status);
+            debug!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+            debug!("stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+            show_command_output(command, output);
+        }
+        Err(e) => {
+            error!("Error running warp-cli {}: {}", command, e);
+            notify(
+                &tr_args("notify-command-failed", &[("command", command)]),
+                &e.to_string(),
+            );
+        }
+    }
+    result
+}
+
+// With restore_mode or reapply_on_upgrade on, a mode picked from the menu is
+// remembered once the daemon reports it. With connect_after_mode_change on, a
+// disconnected WARP is then connected, like picking Connect right after.
+fn set_mode(runner: &dyn CommandRunner, mode: WarpMode, remember: bool, connect: bool) {
+    let succeeded = run_warp_command(runner, "set-mode", &[mode.arg()])
+        .is_ok_and(|output| !command_failed(&output));
+    SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+    if remember
+        && read_warp_settings(runner).and_then(|settings| parse_warp_mode(&settings)) == Some(mode)
+    {
+        state::save_last_mode(mode.arg());
+    }
+    if succeeded && connect && read_warp_status(runner) == WarpStatus::Disconnected {
+        set_paused_until(None);
+        if run_step(runner, "connect") {
+            notify(
+                &tr("notify-mode-connected"),
+                &tr_args("notify-mode-connected-body", &[("mode", mode.arg())]),
+            );
+        }
+    }
+}
+
+// Put back the remembered mode if WARP started up in a different one
+fn restore_last_mode(runner: &dyn CommandRunner) {
+    let Some(mode) = state::load_last_mode().and_then(|arg| WarpMode::from_arg(&arg)) else {
+        return;
+    };
+    let Some(current) = read_warp_settings(runner).and_then(|settings| parse_warp_mode(&settings))
+    else {
+        return;
+    };
+    if current != mode {
+        info!("Restoring mode {} (was {})", mode.arg(), current.arg());
+        let _ = run_warp_command(runner, "set-mode", &[mode.arg()]);
+    }
+}
+
+// An upgrade from the version seen at the last start; the first start has
+// nothing to compare with
+fn warp_cli_upgraded(previous: Option<&str>, current: &str) -> bool {
+    previous.is_some_and(|previous| previous != current)
+}
+
+// Once per new warp-cli version, with reapply_on_upgrade on, set the
+// remembered mode again whatever the daemon reports. Returns whether it did.
+fn reapply_after_upgrade(
+    runner: &dyn CommandRunner,
+    warp_cli: &WarpCliInfo,
+    enabled: bool,
+) -> bool {
+    let Some(version) = warp_cli.version.map(|version| version.to_string()) else {
+        return false;
+    };
+    let previous = state::load_warp_cli_version();
+    if previous.as_deref() != Some(version.as_str()) {
+        state::save_warp_cli_version(&version);
+    }
+    if !enabled || !warp_cli_upgraded(previous.as_deref(), &version) {
+        return false;
+    }
+    let Some(mode) = state::load_last_mode().and_then(|arg| WarpMode::from_arg(&arg)) else {
+        return false;
+    };
+    info!(
+        "warp-cli was upgraded to {}, re-applying mode {}",
+        version,
+        mode.arg()
+    );
+    let result = run_warp_cli(runner, &["set-mode", mode.arg()]);
+    history::record("set-mode", &[mode.arg()], &result);
+    match result {
+        Ok(output) if !command_failed(&output) => notify(
+            &tr("notify-upgrade-reapplied"),
+            &tr_args(
+                "notify-upgrade-reapplied-body",
+                &[("mode", mode.arg()), ("version", &version)],
+            ),
+        ),
+        Ok(output) => show_command_output("set-mode", &output),
+        Err(e) => notify(
+            &tr_args("notify-command-failed", &[("command", "set-mode")]),
+            &e.to_string(),
+        ),
+    }
+    true
+}
+
+// Run at startup once warp-cli is found, before the first status read
+fn apply_saved_settings(runner: &dyn CommandRunner, warp_cli: &WarpCliInfo, config: &Config) {
+    if !reapply_after_upgrade(runner, warp_cli, config.reapply_on_upgrade) && config.restore_mode {
+        restore_last_mode(runner);
+    }
+}
+
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Run one step of a multi-command action, only reporting failures
+fn run_step(runner: &dyn CommandRunner, command: &str) -> bool {
+    info!("Executing: warp-cli {}", command);
+    let result = run_warp_cli(runner, &[command]);
+    history::record(command, &[], &result);
+    request_refresh();
+    match result {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            show_command_output(command, &output);
+            false
+        }
+        Err(e) => {
+            error!("Error running warp-cli {}: {}", command, e);
+            notify(
+                &tr_args("notify-command-failed", &[("command", command)]),
+                &e.to_string(),
+            );
+            false
+        }
+    }
+}
+
+// Bounce the tunnel: disconnect, wait until the daemon reports it, connect
+fn reconnect(runner: &dyn CommandRunner) {
+    if !run_step(runner, "disconnect") {
+        return;
+    }
+
+    let deadline = Instant::now() + RECONNECT_TIMEOUT;
+    while read_warp_status(runner) != WarpStatus::Disconnected {
+        if Instant::now() >= deadline {
+            notify(
+                &tr("notify-reconnect-timed-out"),
+                &tr_args(
+                    "notify-reconnect-timed-out-body",
+                    &[("secs", &RECONNECT_TIMEOUT.as_secs().to_string())],
+                ),
+            );
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    if run_step(runner, "connect") {
+        notify(&tr("notify-reconnected"), &tr("notify-reconnected-body"));
+    }
+}
+
+// Whether traffic really goes through WARP, from the "warp=on" style lines
+// trace-support shares with cloudflare.com/cdn-cgi/trace. "plus" is WARP+.
+fn parse_trace_warp(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("warp="))
+        .map(str::trim)
+}
+
+fn check_connectivity(runner: &dyn CommandRunner) {
+    info!("Executing: warp-cli trace-support");
+    let result = run_warp_cli(runner, &["trace-support"]);
+    history::record("trace-support", &[], &result);
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Error running warp-cli trace-support: {}", e);
+            notify(
+                &tr_args("notify-command-failed", &[("command", "trace-support")]),
+                &e.to_string(),
+            );
+            return;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("stdout:\n{}", stdout);
+    match parse_trace_warp(&stdout) {
+        Some(value @ ("on" | "plus")) => notify(
+            &tr("notify-protected"),
+            &tr_args("notify-protected-body", &[("value", value)]),
+        ),
+        Some(value) => notify(
+            &tr("notify-not-protected"),
+            &tr_args("notify-not-protected-body", &[("value", value)]),
+        ),
+        None => show_command_output("trace-support", &output),
+    }
+}
+
+// When a pause from the menu ends; the poll timer reconnects once it passes
+static PAUSED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn paused_until() -> Option<Instant> {
+    *PAUSED_UNTIL.lock().unwrap()
+}
+
+fn set_paused_until(until: Option<Instant>) {
+    *PAUSED_UNTIL.lock().unwrap() = until;
+}
+
+// pause_minutes was checked when the config was loaded
+fn pause_duration(config: &Config) -> Duration {
+    Duration::from_secs(config.pause_minutes * 60)
+}
+
+// Whole minutes, rounded up, e.g. "1h" or "42m"
+fn format_minutes(duration: Duration) -> String {
+    let minutes = duration.as_secs().div_ceil(60);
+    humantime::format_duration(Duration::from_secs(minutes * 60)).to_string()
+}
+
+// Seconds for the first minute, then whole minutes, e.g. "1h 23m"
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let shown = if secs < 60 { secs } else { secs - secs % 60 };
+    humantime::format_duration(Duration::from_secs(shown)).to_string()
+}
+
+// Disconnect, e.g. to get through a captive portal, and come back later
+fn pause(runner: &dyn CommandRunner, duration: Duration) {
+    if run_step(runner, "disconnect") {
+        set_paused_until(Some(Instant::now() + duration));
+        notify(
+            &tr("notify-paused"),
+            &tr_args(
+                "notify-paused-body",
+                &[("duration", &format_minutes(duration))],
+            ),
+        );
+    }
+}
+
+fn resume(runner: &dyn CommandRunner) {
+    set_paused_until(None);
+    if run_step(runner, "connect") {
+        notify(&tr("notify-resumed"), &tr("notify-resumed-body"));
+    }
+}
+
+// warp-cli sometimes exits successfully while reporting an error on stderr,
+// so that counts as failure too
+fn command_failed(output: &Output) -> bool {
+    !output.status.success()
+        || String::from_utf8_lossy(&output.stderr)
+            .trim_start()
+            .starts_with("Error")
+}
+
+// Summarize a finished command in a notification
+fn show_command_output(command: &str, output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let (summary, body) = if !command_failed(output) {
+        (
+            tr_args("notify-command-succeeded", &[("command", command)]),
+            stdout.lines().next().unwrap_or_default().to_string(),
+        )
+    } else {
+        let reason = match output.status.code() {
+            Some(0) => tr("notify-failed-with-error"),
+            Some(code) => tr_args(
+                "notify-failed-with-exit-code",
+                &[("code", &code.to_string())],
+            ),
+            None => tr("notify-failed-with-signal"),
+        };
+        (
+            tr_args(
+                "notify-command-failed-reason",
+                &[("command", command), ("reason", &reason)],
+            ),
+            stderr.trim().to_string(),
+        )
+    };
+    notify(&summary, &body);
+
+    if FULL_OUTPUT_COMMANDS.contains(&command) {
+        show_output_window(
+            format!("warp-cli {}", command),
+            format!("{}{}", stdout, stderr),
+        );
+    }
+}
+
+// warp-cli prints where it wrote the report zip, e.g.
+// "Report saved to /tmp/warp-debugging-info-20240101.zip"
+fn parse_report_path(stdout: &str) -> Option<PathBuf> {
+    stdout
+        .split_whitespace()
+        .map(|token| token.trim_matches(|c: char| c == '"' || c == '\'' || c == ','))
+        .find(|token| token.ends_with(".zip"))
+        .map(PathBuf::from)
+}
+
+fn generate_report(runner: &dyn CommandRunner) {
+    info!("Executing: warp-cli generate-report");
+    let result = run_warp_cli(runner, &["generate-report"]);
+    history::record("generate-report", &[], &result);
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Error running warp-cli generate-report: {}", e);
+            notify(
+                &tr_args("notify-command-failed", &[("command", "generate-report")]),
+                &e.to_string(),
+            );
+            return;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    info!("warp-cli generate-report exited with {}", output.status);
+    debug!("stdout:\n{}", stdout);
+    if !output.status.success() {
+        show_command_output("generate-report", &output);
+        return;
+    }
+
+    match parse_report_path(&stdout) {
+        Some(path) => notify_report_saved(path),
+        None => notify("warp-cli generate-report", &tr("notify-report-generated")),
+    }
+}
+
+// Opens a file or folder with the desktop's default application
+#[cfg(all(unix, not(target_os = "macos")))]
+const OPEN_COMMAND: &str = "xdg-open";
+#[cfg(target_os = "macos")]
+const OPEN_COMMAND: &str = "open";
+#[cfg(windows)]
+const OPEN_COMMAND: &str = "explorer";
+
+fn open_on_host(runner: &dyn CommandRunner, path: &Path) -> io::Result<Output> {
+    run_host_command(runner, OsStr::new(OPEN_COMMAND), &[&path.to_string_lossy()])
+}
+
+// Where the WARP daemon keeps its settings and logs, unless the config says
+// otherwise
+#[cfg(all(unix, not(target_os = "macos")))]
+const DEFAULT_WARP_DIR: &str = "/var/lib/cloudflare-warp";
+#[cfg(target_os = "macos")]
+const DEFAULT_WARP_DIR: &str = "/Library/Application Support/Cloudflare";
+#[cfg(windows)]
+const DEFAULT_WARP_DIR: &str = r"C:\ProgramData\Cloudflare";
+
+fn warp_dir(config: &Config) -> PathBuf {
+    config
+        .warp_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_WARP_DIR))
+}
+
+// A sandbox can't look at the host's folders, so there the opener has to
+// report a missing one
+fn open_warp_dir(runner: &dyn CommandRunner, dir: &Path) {
+    if !host_spawn() && !dir.is_dir() {
+        notify(
+            &tr("notify-warp-dir-not-found"),
+            &tr_args(
+                "notify-warp-dir-not-found-body",
+                &[("dir", &dir.display().to_string())],
+            ),
+        );
+        return;
+    }
+    if let Err(e) = open_on_host(runner, dir) {
+        error!("Error opening {}: {}", dir.display(), e);
+        notify(&tr("notify-open-warp-dir-failed"), &e.to_string());
+    }
+}
+
+const DIAGNOSTIC_COMMANDS: [&[&str]; 3] = [&["status"], &["settings"], &["--version"]];
+
+// What a redacted value is replaced with
+const REDACTED: &str = "***";
+
+// Keys of the fields that tie a report to an account or device, lowercased.
+// They are always left out, since a report is meant to be shared.
+const ID_FIELDS: [&str; 4] = ["account id", "device id", "registration id", "client id"];
+
+// Keys of the identity fields [redact] covers, lowercased
+const ORGANIZATION_FIELDS: [&str; 3] = ["organization", "organization name", "team name"];
+const EMAIL_FIELDS: [&str; 3] = ["email", "account email", "user email"];
+const DEVICE_NAME_FIELDS: [&str; 3] = ["device name", "device", "hostname"];
+
+fn is_redacted_key(key: &str, redact: &Redact) -> bool {
+    let key = key.to_lowercase();
+    let key = key.as_str();
+    ID_FIELDS.contains(&key)
+        || (redact.organization && ORGANIZATION_FIELDS.contains(&key))
+        || (redact.email && EMAIL_FIELDS.contains(&key))
+        || (redact.device_name && DEVICE_NAME_FIELDS.contains(&key))
+}
+
+// The key of a "Key: value" line, without the source prefix settings lines
+// may have (see `field`)
+fn line_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once(':')?;
+    let key = key.trim();
+    let key = match key.strip_prefix('(') {
+        Some(rest) => rest.split_once(')')?.1,
+        None => key,
+    };
+    Some(key.trim())
+}
+
+// Account and device IDs are UUIDs, which also turn up without a key
+fn is_uuid(token: &str) -> bool {
+    token.len() == 36
+        && token.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// An address, with or without a port, or a range like 10.0.0.0/8
+fn is_ip_address(token: &str) -> bool {
+    let address = token
+        .split_once('/')
+        .filter(|(_, prefix)| prefix.parse::<u8>().is_ok())
+        .map_or(token, |(address, _)| address);
+    address.parse::<IpAddr>().is_ok() || address.parse::<SocketAddr>().is_ok()
+}
+
+fn is_email(token: &str) -> bool {
+    token.split_once('@').is_some_and(|(user, domain)| {
+        !user.is_empty() && !domain.contains('@') && domain.contains('.')
+    })
+}
+
+// Addresses and emails are found word by word, wherever they are
+fn redact_words(line: &str, redact: &Redact) -> String {
+    line.split_inclusive(char::is_whitespace)
+        .map(|chunk| {
+            let word = chunk.trim_end();
+            let token = word.trim_matches(|c: char| matches!(c, ',' | ';' | '.' | '(' | ')' | '"'));
+            if !token.is_empty()
+                && ((redact.ip_addresses && is_ip_address(token))
+                    || (redact.email && is_email(token)))
+            {
+                chunk.replacen(token, REDACTED, 1)
+            } else {
+                chunk.to_string()
+            }
+        })
+        .collect()
+}
+
+fn redact_uuids(line: &str) -> String {
+    let mut redacted = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_hexdigit()) {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_hexdigit() && c != '-')
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        redacted.push_str(if is_uuid(token) { REDACTED } else { token });
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// Each line with the values of the redacted fields, any UUID and, if
+// `redact` says so, addresses and emails replaced
+fn redact_diagnostics(text: &str, redact: &Redact) -> String {
+    text.lines()
+        .map(|line| {
+            let redacted_key = line_key(line).is_some_and(|key| is_redacted_key(key, redact));
+            let line = match line.split_once(':') {
+                Some((key, _)) if redacted_key => format!("{}: {}", key, REDACTED),
+                _ => redact_words(&redact_uuids(line), redact),
+            };
+            line + "\n"
+        })
+        .collect()
+}
+
+// Everything a bug report needs, each command's output under its own header
+fn collect_diagnostics(runner: &dyn CommandRunner, redact: &Redact) -> String {
+    let mut text = format!("warp-taskbar {}\n", cli::VERSION);
+    for args in DIAGNOSTIC_COMMANDS {
+        text.push_str(&format!("\n== warp-cli {} ==\n", args.join(" ")));
+        match run_warp_cli(runner, args) {
+            Ok(output) => {
+                text.push_str(&String::from_utf8_lossy(&output.stdout));
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+            }
+            Err(e) => text.push_str(&format!("Failed to run: {}\n", e)),
+        }
+    }
+    redact_diagnostics(&text, redact)
+}
+
+// In the user's runtime dir if there is one, else the temp dir. The name is
+// new each time and the file must not exist yet, so nothing planted under
+// that name (a symlink elsewhere) is ever written through.
+fn save_diagnostics(text: &str) -> io::Result<PathBuf> {
+    use std::io::Write;
+
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(env::temp_dir, PathBuf::from);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    let path = dir.join(format!(
+        "warp-taskbar-diagnostics-{}-{}.txt",
+        std::process::id(),
+        millis
+    ));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(text.as_bytes())?;
+    Ok(path)
+}
+
+// The text is also saved to a file, for bug trackers that take attachments
+fn copy_diagnostics(runner: &dyn CommandRunner, redact: &Redact) {
+    let text = collect_diagnostics(runner, redact);
+    copy_to_clipboard(text.clone());
+    let body = match save_diagnostics(&text) {
+        Ok(path) => tr_args(
+            "notify-diagnostics-saved",
+            &[("path", &path.display().to_string())],
+        ),
+        Err(e) => {
+            warn!("Failed to save the diagnostics: {}", e);
+            tr("notify-diagnostics-copied")
+        }
+    };
+    notify(&tr("notify-diagnostics"), &body);
+}
+
+// The clipboard belongs to GTK, on the main thread. store() keeps the text
+// available after warp-taskbar exits, where a clipboard manager runs.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn copy_to_clipboard(text: String) {
+    glib::idle_add_once(move || {
+        let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
+        clipboard.set_text(&text);
+        clipboard.store();
+    });
+}
+
+// Both read the text to copy from stdin
+#[cfg(target_os = "macos")]
+const CLIPBOARD_COMMAND: &str = "pbcopy";
+#[cfg(windows)]
+const CLIPBOARD_COMMAND: &str = "clip";
+
+#[cfg(any(windows, target_os = "macos"))]
+fn copy_to_clipboard(text: String) {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let result = Command::new(CLIPBOARD_COMMAND)
+        .stdin(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            // Dropping stdin closes it, so the command sees the end of the text
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(text.as_bytes())?;
+            }
+            child.wait()
+        });
+    if let Err(e) = result {
+        error!("Error running {}: {}", CLIPBOARD_COMMAND, e);
+    }
+}
+
+// A notification with one button, which runs `on_click`. notify-send
+// --wait only returns once the notification is closed, so this runs on its
+// own thread, as does `on_click`. A notify-send without --action support
+// fails and falls back to a plain notification; a notification server
+// without actions just never reports a click.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn notify_with_action<F>(summary: String, body: String, button: &str, on_click: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let action = format!("--action=clicked={}", button);
+    std::thread::spawn(move || {
+        let result = Command::new("notify-send")
+            .args([
+                "--app-name",
+                "warp-taskbar",
+                &action,
+                "--wait",
+                &summary,
+                &body,
+            ])
+            .output();
+        match result {
+            Ok(output) if output.status.success() => {
+                if String::from_utf8_lossy(&output.stdout).trim() == "clicked" {
+                    on_click();
+                }
+            }
+            _ => notify(&summary, &body),
+        }
+    });
+}
+
+// Elsewhere the notification comes without the button
+#[cfg(any(windows, target_os = "macos"))]
+fn notify_with_action<F>(summary: String, body: String, _button: &str, _on_click: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    notify(&summary, &body);
+}
+
+fn notify_report_saved(path: PathBuf) {
+    let body = tr_args(
+        "notify-report-saved",
+        &[("path", &path.display().to_string())],
+    );
+    notify_with_action(
+        "warp-cli generate-report".to_string(),
+        body,
+        &tr("notify-open-folder"),
+        move || {
+            if let Some(folder) = path.parent() {
+                let _ = open_on_host(&SystemRunner, folder);
+            }
+        },
+    );
+}
+
+// Shares the Connect item's guard, so a click here and a menu pick don't race
+fn notify_disconnected(body: String) {
+    notify_with_action(
+        "Cloudflare WARP".to_string(),
+        body,
+        &tr("notify-reconnect"),
+        || {
+            let Some(_guard) = ActionGuard::acquire("connect") else {
+                return;
+            };
+            set_paused_until(None);
+            let _ = run_warp_command(&SystemRunner, "connect", &[]);
+        },
+    );
+}
+
+// GTK widgets may only be touched from the main thread
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show_output_window(title: String, text: String) {
+    glib::idle_add_once(move || show_output_dialog(&title, &text));
+}
+
+// A message box blocks its caller, so give it a thread of its own
+#[cfg(windows)]
+fn show_output_window(title: String, text: String) {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_OK};
+
+    std::thread::spawn(move || {
+        let wide = |s: &str| s.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+        let (title, text) = (wide(&title), wide(&text));
+        unsafe {
+            MessageBoxW(std::ptr::null_mut(), text.as_ptr(), title.as_ptr(), MB_OK);
+        }
+    });
+}
+
+// osascript blocks until the dialog is dismissed
+#[cfg(target_os = "macos")]
+fn show_output_window(title: String, text: String) {
+    std::thread::spawn(move || {
+        let script = format!(
+            "display dialog {} with title {} buttons {{{}}} default button 1",
+            applescript_string(&text),
+            applescript_string(&title),
+            applescript_string(&tr("dialog-close"))
+        );
+        let _ = Command::new("osascript").args(["-e", &script]).status();
+    });
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show_output_dialog(title: &str, text: &str) {
+    let dialog = gtk::Dialog::with_buttons(
+        Some(title),
+        None::<&gtk::Window>,
+        gtk::DialogFlags::empty(),
+        &[(&tr("dialog-close"), gtk::ResponseType::Close)],
+    );
+    dialog.set_default_size(600, 400);
+
+    let buffer = gtk::TextBuffer::new(None::<&gtk::TextTagTable>);
+    buffer.set_text(text);
+    let view = gtk::TextView::with_buffer(&buffer);
+    view.set_editable(false);
+    view.set_monospace(true);
+
+    let scrolled = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+    scrolled.set_vexpand(true);
+    scrolled.add(&view);
+    dialog.content_area().add(&scrolled);
+
+    dialog.connect_response(|dialog, _| dialog.close());
+    dialog.show_all();
+}
+
+fn warp_cli_version_text() -> String {
+    match detect_warp_cli(&SystemRunner) {
+        Some(WarpCliInfo {
+            version: Some(version),
+        }) => version.to_string(),
+        Some(_) => "unknown version".to_string(),
+        None => "not found".to_string(),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show_about() {
+    glib::idle_add_once(|| {
+        let dialog = gtk::AboutDialog::new();
+        dialog.set_program_name("warp-taskbar");
+        dialog.set_version(Some(cli::VERSION));
+        dialog.set_comments(Some(&format!(
+            "{}\nwarp-cli: {}",
+            tr("dialog-about"),
+            warp_cli_version_text()
+        )));
+        dialog.set_website(Some(env!("CARGO_PKG_REPOSITORY")));
+        dialog.set_license_type(gtk::License::MitX11);
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.show_all();
+    });
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+fn show_about() {
+    show_output_window(
+        tr("dialog-about-title"),
+        format!(
+            "warp-taskbar {}\n{}\n\nwarp-cli: {}\n\n{}",
+            cli::VERSION,
+            tr("dialog-about"),
+            warp_cli_version_text(),
+            env!("CARGO_PKG_REPOSITORY")
+        ),
+    );
+}
+
+// Called from the menu thread, which waits for the answer while the dialog
+// runs on the main thread
+#[cfg(all(unix, not(target_os = "macos")))]
+fn confirm(question: String) -> bool {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    glib::idle_add_once(move || {
+        let dialog = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::YesNo,
+            &question,
+        );
+        dialog.set_title("warp-taskbar");
+        dialog.connect_response(move |dialog, response| {
+            let _ = sender.send(response == gtk::ResponseType::Yes);
+            dialog.close();
+        });
+        dialog.show_all();
+    });
+    wait_for_answer(receiver).unwrap_or(false)
+}
+
+// The dialogs answer through a response callback rather than dialog.run(),
+// whose nested main loop would keep a SIGTERM from ending the main one until
+// the dialog is answered. A dialog still open at Quit counts as cancelled.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn wait_for_answer<T>(receiver: mpsc::Receiver<T>) -> Option<T> {
+    loop {
+        match receiver.recv_timeout(EVENT_WAIT) {
+            Ok(answer) => return Some(answer),
+            Err(mpsc::RecvTimeoutError::Timeout) if !QUIT_REQUESTED.load(Ordering::SeqCst) => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+// A line of text from the user, None if they cancel. Like confirm, the
+// dialog itself runs on the main thread.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn ask_text(question: String, initial: String) -> Option<String> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    glib::idle_add_once(move || {
+        let dialog = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &question,
+        );
+        dialog.set_title("warp-taskbar");
+        dialog.set_default_response(gtk::ResponseType::Ok);
+        let entry = gtk::Entry::new();
+        entry.set_text(&initial);
+        entry.set_activates_default(true);
+        dialog.content_area().pack_end(&entry, false, false, 0);
+        dialog.connect_response(move |dialog, response| {
+            let text = entry.text().to_string();
+            let _ = sender.send((response == gtk::ResponseType::Ok).then_some(text));
+            dialog.close();
+        });
+        dialog.show_all();
+    });
+    wait_for_answer(receiver).flatten()
+}
+
+#[cfg(windows)]
+fn confirm(question: String) -> bool {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        MessageBoxW, IDYES, MB_ICONQUESTION, MB_YESNO,
+    };
+
+    let wide = |s: &str| s.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+    let (title, text) = (wide("warp-taskbar"), wide(&question));
+    let answer = unsafe {
+        MessageBoxW(
+            std::ptr::null_mut(),
+            text.as_ptr(),
+            title.as_ptr(),
+            MB_YESNO | MB_ICONQUESTION,
+        )
+    };
+    answer == IDYES
+}
+
+// Windows has no input box of its own, but PowerShell can borrow the one
+// from Visual Basic. It returns an empty string when cancelled.
+#[cfg(windows)]
+fn ask_text(question: String, initial: String) -> Option<String> {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let script = format!(
+        "Add-Type -AssemblyName Microsoft.VisualBasic; \
+         [Microsoft.VisualBasic.Interaction]::InputBox({}, 'warp-taskbar', {})",
+        quote(&question),
+        quote(&initial)
+    );
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+// A toast shown through PowerShell. warp-taskbar has no app id of its own
+// registered with Windows, so the toast goes out under PowerShell's. It is
+// not waited for, since PowerShell takes a moment to start.
+#[cfg(windows)]
+fn desktop_notify(summary: &str, body: &str) {
+    use std::os::windows::process::CommandExt;
+    use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
+
+    const POWERSHELL_APP_ID: &str =
+        r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let script = format!(
+        "$manager = [Windows.UI.Notifications.ToastNotificationManager, \
+         Windows.UI.Notifications, ContentType = WindowsRuntime]; \
+         $xml = $manager::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $text = $xml.GetElementsByTagName('text'); \
+         [void]$text.Item(0).AppendChild($xml.CreateTextNode({})); \
+         [void]$text.Item(1).AppendChild($xml.CreateTextNode({})); \
+         $manager::CreateToastNotifier({}).Show([Windows.UI.Notifications.ToastNotification]::new($xml))",
+        quote(summary),
+        quote(body),
+        quote(POWERSHELL_APP_ID)
+    );
+    let _ = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn();
+}
+
+// osascript prints the button and the text, e.g. "button returned:OK, text
+// returned:abc", and fails when cancelled
+#[cfg(target_os = "macos")]
+fn ask_text(question: String, initial: String) -> Option<String> {
+    let script = format!(
+        "display dialog {} with title \"warp-taskbar\" default answer {}",
+        applescript_string(&question),
+        applescript_string(&initial)
+    );
+    let output = Command::new("osascript")
+        .args(["-e", &script])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, text) = stdout.split_once("text returned:")?;
+    Some(text.trim_end_matches('\n').to_string())
+}
+
+// osascript prints the clicked button, e.g. "button returned:Yes"
+#[cfg(target_os = "macos")]
+fn confirm(question: String) -> bool {
+    let (yes, no) = (tr("dialog-yes"), tr("dialog-no"));
+    let script = format!(
+        "display dialog {} with title \"warp-taskbar\" buttons {{{}, {}}} default button {}",
+        applescript_string(&question),
+        applescript_string(&no),
+        applescript_string(&yes),
+        applescript_string(&yes)
+    );
+    let answer = format!("button returned:{}", yes);
+    Command::new("osascript")
+        .args(["-e", &script])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim_end() == answer)
+}
+
+#[cfg(target_os = "macos")]
+fn desktop_notify(summary: &str, body: &str) {
+    let script = format!(
+        "display notification {} with title {}",
+        applescript_string(body),
+        applescript_string(summary)
+    );
+    let _ = Command::new("osascript").args(["-e", &script]).status();
+}
+
+#[cfg(target_os = "macos")]
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Print a single status block for i3blocks and exit.
+//
+// i3blocks reads up to three lines from a blocklet: full text, short text and
+// color. Point a blocklet at this binary to use it:
+//
+//     [warp]
+//     command=warp-taskbar --i3blocks
+//     interval=5
+fn print_i3blocks_status() {
+    let status = read_warp_status(&SystemRunner);
+    let (short_text, color) = i3blocks_short_text_and_color(status);
+    println!("WARP: {}", status.label());
+    println!("{}", short_text);
+    println!("{}", color);
+}
+
+// Every status gets a color of its own, so the block tells them apart at a
+// glance
+fn i3blocks_short_text_and_color(status: WarpStatus) -> (&'static str, &'static str) {
+    match status {
+        WarpStatus::Connected => ("WARP", "#00FF00"),
+        WarpStatus::CaptivePortal => ("WARP !", "#FFFF00"),
+        WarpStatus::Connecting => ("WARP ...", "#00BFFF"),
+        WarpStatus::Disconnected => ("WARP off", "#FF0000"),
+        WarpStatus::UnableToConnect => ("WARP x", "#FF8000"),
+        WarpStatus::RegistrationMissing => ("WARP reg", "#FF00FF"),
+        WarpStatus::DaemonNotRunning => ("WARP svc", "#808080"),
+        WarpStatus::Unknown => ("WARP ?", "#FFFFFF"),
+    }
+}
+
+// What `warp-taskbar status` reports, also as JSON for scripts
+#[derive(Serialize)]
+struct StatusReport {
+    status: WarpStatus,
+    mode: Option<&'static str>,
+    account: Option<String>,
+}
+
+fn print_status(json: bool) -> ExitCode {
+    let runner = SystemRunner;
+    let status = read_warp_status(&runner);
+    let report = StatusReport {
+        status,
+        mode: read_warp_settings(&runner)
+            .and_then(|settings| parse_warp_mode(&settings))
+            .map(WarpMode::arg),
+        account: read_warp_account(&runner),
+    };
+    if json {
+        match serde_json::to_string(&report) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("warp-taskbar: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        println!("Status: {}", status.label());
+        println!("Mode: {}", report.mode.unwrap_or("unknown"));
+        println!(
+            "Account: {}",
+            report.account.as_deref().unwrap_or("unknown")
+        );
+    }
+    if status.is_unreadable() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+// Whether the tray last saw WARP connected, kept for the connectivity probe
+// so it doesn't read the status a second time
+static WARP_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+// Global unicast, 2000::/3, as opposed to link-local or unique local
+fn is_global_ipv6(ip: Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xe000 == 0x2000
+}
+
+// Whether this host has a route to `target` from a global IPv6 address.
+// Connecting a UDP socket only picks the route, nothing is sent.
+fn has_global_ipv6_route(target: SocketAddr) -> bool {
+    let Ok(socket) = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)) else {
+        return false;
+    };
+    socket.connect(target).is_ok()
+        && socket
+            .local_addr()
+            .is_ok_and(|addr| matches!(addr.ip(), IpAddr::V6(ip) if is_global_ipv6(ip)))
+}
+
+// Sleeps for `duration` unless Quit comes first, in which case it returns
+// false. join_event_threads unparks the thread to cut the wait short.
+fn sleep_unless_quit(duration: Duration) -> bool {
+    let until = Instant::now() + duration;
+    loop {
+        if QUIT_REQUESTED.load(Ordering::SeqCst) {
+            return false;
+        }
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        std::thread::park_timeout(left);
+    }
+}
+
+// Optional reachability probe, turned on with connectivity_probe in the config.
+//
+// While WARP is connected, try to reach 1.1.1.1 every 30 seconds, and its
+// IPv6 address too when this host has an IPv6 route. After three failures in
+// a row show a single hint naming what was unreachable, since broken IPv6 is
+// a common cause. The hint is shown again only after a success.
+fn spawn_connectivity_probe() -> JoinHandle<()> {
+    const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+    const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+    const FAILURES_BEFORE_HINT: u32 = 3;
+
+    std::thread::spawn(|| {
+        let ipv4 = SocketAddr::from(([1, 1, 1, 1], 443));
+        let ipv6 = SocketAddr::from(([0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111], 443));
+        let unreachable = |target| TcpStream::connect_timeout(&target, PROBE_TIMEOUT).is_err();
+        let mut failures = 0;
+        while sleep_unless_quit(PROBE_INTERVAL) {
+            if !WARP_CONNECTED.load(Ordering::SeqCst) {
+                failures = 0;
+                continue;
+            }
+
+            let mut failed = Vec::new();
+            if unreachable(ipv4) {
+                failed.push(ipv4.ip().to_string());
+            }
+            if has_global_ipv6_route(ipv6) && unreachable(ipv6) {
+                failed.push(ipv6.ip().to_string());
+            }
+            if failed.is_empty() {
+                failures = 0;
+                continue;
+            }
+
+            failures += 1;
+            if failures == FAILURES_BEFORE_HINT {
+                notify(
+                    &tr("notify-connectivity-problem"),
+                    &tr_args(
+                        "notify-connectivity-problem-body",
+                        &[("addresses", &failed.join(", "))],
+                    ),
+                );
+            }
+        }
+    })
+}
+
+const DEFAULT_POLL_SECS: u64 = 2;
+const MAX_POLL_BACKOFF: Duration = Duration::from_secs(30);
+
+// How many timer ticks to skip after `failures` consecutive unreadable
+// statuses: the effective interval doubles per failure up to MAX_POLL_BACKOFF.
+fn backoff_ticks(interval: Duration, failures: u32) -> u32 {
+    let backoff = interval
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_POLL_BACKOFF);
+    let ticks = backoff.as_secs() / interval.as_secs().max(1);
+    u32::try_from(ticks).unwrap_or(u32::MAX).saturating_sub(1)
+}
+
+// Values below one second are raised to one to avoid busy-polling warp-cli
+fn poll_interval(config: &Config) -> Duration {
+    let secs = config.poll_secs.unwrap_or(DEFAULT_POLL_SECS);
+    Duration::from_secs(secs.max(1))
+}
+
+// Offered in the Refresh Rate submenu, in seconds
+const POLL_PRESETS: [u64; 4] = [1, 2, 5, 10];
+
+// Menu ids of refresh rate items are this prefix plus the seconds
+const POLL_SECS_ID_PREFIX: &str = "poll_secs:";
+
+// A refresh rate picked from the menu, waiting for the main loop to restart
+// its poll timer with it
+static POLL_INTERVAL_REQUEST: Mutex<Option<Duration>> = Mutex::new(None);
+
+fn take_poll_interval_request() -> Option<Duration> {
+    POLL_INTERVAL_REQUEST.lock().unwrap().take()
+}
+
+fn set_refresh_rate(secs: u64) {
+    *POLL_INTERVAL_REQUEST.lock().unwrap() = Some(Duration::from_secs(secs));
+    wake_main_loop();
+    if let Err(e) = config::save_poll_secs(secs) {
+        warn!("Failed to save the refresh rate: {}", e);
+        notify(&tr("notify-refresh-rate-not-saved"), &e.to_string());
+    }
+}
+
+struct AppIcons {
+    cloudflare_connecting: &'static [u8],
+    cloudflare_dark_active: &'static [u8],
+    cloudflare_inactive: &'static [u8],
+    cloudflare_light_active: &'static [u8],
+}
+
+const APP_ICONS: AppIcons = AppIcons {
+    cloudflare_connecting: include_bytes!("../icon/cloudflare-connecting.ico"),
+    cloudflare_dark_active: include_bytes!("../icon/cloudflare-dark-active.ico"),
+    cloudflare_inactive: include_bytes!("../icon/cloudflare-inactive.ico"),
+    cloudflare_light_active: include_bytes!("../icon/cloudflare-light-active.ico"),
+};
+
+// Monochrome icons for symbolic tray themes. The connected icon is
+// recolored for the panel like any other SVG; the others stay grey.
+const SYMBOLIC_ICONS: AppIcons = AppIcons {
+    cloudflare_connecting: include_bytes!("../icon/cloudflare-symbolic.svg"),
+    cloudflare_dark_active: include_bytes!("../icon/cloudflare-symbolic.svg"),
+    cloudflare_inactive: include_bytes!("../icon/cloudflare-symbolic-inactive.svg"),
+    cloudflare_light_active: include_bytes!("../icon/cloudflare-symbolic.svg"),
+};
+
+fn bundled_icons(style: IconStyle) -> &'static AppIcons {
+    let symbolic = match style {
+        IconStyle::Auto => theme::prefers_symbolic_icons(),
+        IconStyle::Color => false,
+        IconStyle::Symbolic => true,
+    };
+    if symbolic {
+        &SYMBOLIC_ICONS
+    } else {
+        &APP_ICONS
+    }
+}
+
+// Icon bytes from a user-supplied file, or the bundled icon if unset/unreadable
+fn icon_bytes(path: Option<&Path>, bundled: &'static [u8]) -> Cow<'static, [u8]> {
+    let Some(path) = path else {
+        return Cow::Borrowed(bundled);
+    };
+    match fs::read(path) {
+        Ok(bytes) => Cow::Owned(bytes),
+        Err(e) => {
+            warn!("Failed to read icon {}: {}", path.display(), e);
+            Cow::Borrowed(bundled)
+        }
+    }
+}
+
+// SVG icons are rasterized at this size when the panel's size is unknown
+const SVG_ICON_SIZE: u32 = 32;
+
+// The small icon size Windows uses in the notification area, at the
+// system DPI
+#[cfg(windows)]
+fn panel_icon_size() -> Option<u32> {
+    use windows_sys::Win32::UI::HiDpi::{GetDpiForSystem, GetSystemMetricsForDpi};
+    use windows_sys::Win32::UI::WindowsAndMessaging::SM_CXSMICON;
+
+    let size = unsafe { GetSystemMetricsForDpi(SM_CXSMICON, GetDpiForSystem()) };
+    u32::try_from(size).ok().filter(|&size| size > 0)
+}
+
+// StatusNotifierItem hosts and the macOS status bar don't report a size
+#[cfg(not(windows))]
+fn panel_icon_size() -> Option<u32> {
+    None
+}
+
+// icon_size from the config, checked when it was loaded, wins over what the
+// platform reports. None leaves bitmaps at their own size.
+fn icon_size(config: &Config) -> Option<u32> {
+    config.icon_size.or_else(panel_icon_size)
+}
+
+// Icons drawn in a single color are recolored to suit the panel
+const DARK_ICON_COLOR: [u8; 3] = [0x20, 0x20, 0x20];
+const LIGHT_ICON_COLOR: [u8; 3] = [0xF0, 0xF0, 0xF0];
+
+// Sniff the start of the file, since icon paths may have any extension
+fn is_svg(image_data: &[u8]) -> bool {
+    let head = &image_data[..image_data.len().min(1024)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+fn invalid_icon(message: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn rasterize_svg(image_data: &[u8], size: u32) -> io::Result<image::RgbaImage> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree =
+        usvg::Tree::from_data(image_data, &usvg::Options::default()).map_err(invalid_icon)?;
+    let mut pixmap = tiny_skia::Pixmap::new(size, size)
+        .ok_or_else(|| invalid_icon(format!("invalid icon size {}", size)))?;
+    let scale = (size as f32 / tree.size().width()).min(size as f32 / tree.size().height());
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    // tiny-skia stores premultiplied alpha, tray icons expect straight alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    Ok(image::RgbaImage::from_raw(size, size, pixels).expect("Pixmap size mismatch"))
+}
+
+// Bitmaps are scaled to fit `size` keeping their aspect ratio
+fn decode_icon(image_data: &[u8], size: Option<u32>) -> io::Result<image::RgbaImage> {
+    if is_svg(image_data) {
+        return rasterize_svg(image_data, size.unwrap_or(SVG_ICON_SIZE));
+    }
+    let image = image::load_from_memory(image_data).map_err(invalid_icon)?;
+    Ok(match size {
+        Some(size) if image.width().max(image.height()) != size => image
+            .resize(size, size, image::imageops::FilterType::Lanczos3)
+            .to_rgba8(),
+        _ => image.to_rgba8(),
+    })
+}
+
+fn tray_icon_from_rgba(image: image::RgbaImage) -> Icon {
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).expect("Failed to create tray icon")
+}
+
+// An SVG is painted in `color`, keeping its shape's alpha, so a single file
+// serves both themes. Bitmaps are used as they are.
+fn decode_themed_icon(
+    image_data: &[u8],
+    size: Option<u32>,
+    color: [u8; 3],
+) -> io::Result<image::RgbaImage> {
+    let mut image = decode_icon(image_data, size)?;
+    if is_svg(image_data) {
+        for pixel in image.pixels_mut() {
+            pixel[0] = color[0];
+            pixel[1] = color[1];
+            pixel[2] = color[2];
+        }
+    }
+    Ok(image)
+}
+
+// A user-supplied icon that can't be read or decoded is replaced by the
+// bundled one, so a bad file never takes the tray down
+fn load_icon_image(
+    path: Option<&Path>,
+    bundled: &'static [u8],
+    size: Option<u32>,
+    color: Option<[u8; 3]>,
+) -> image::RgbaImage {
+    let decode = |image_data: &[u8], size| match color {
+        Some(color) => decode_themed_icon(image_data, size, color),
+        None => decode_icon(image_data, size),
+    };
+    match decode(&icon_bytes(path, bundled), size) {
+        Ok(image) => image,
+        Err(e) => {
+            if let Some(path) = path {
+                warn!("Failed to decode icon {}: {}", path.display(), e);
+            }
+            // A size the bundled icon can't be drawn at gets the default one,
+            // and as a last resort the icon is left blank
+            decode(bundled, size)
+                .or_else(|e| {
+                    warn!("Failed to draw the bundled icon at {:?}: {}", size, e);
+                    decode(bundled, None)
+                })
+                .unwrap_or_else(|e| {
+                    error!("Failed to draw the bundled icon: {}", e);
+                    image::RgbaImage::new(SVG_ICON_SIZE, SVG_ICON_SIZE)
+                })
+        }
+    }
+}
+
+// The second frame of the connecting animation: the same icon, faded
+fn faded_tray_icon(mut image: image::RgbaImage) -> Icon {
+    for pixel in image.pixels_mut() {
+        pixel[3] /= 3;
+    }
+    tray_icon_from_rgba(image)
+}
+
+// How long each frame of the connecting animation is shown
+const CONNECTING_FRAME_INTERVAL: Duration = Duration::from_millis(500);
+
+// Which of the tray icons is currently shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IconState {
+    Connecting,
+    Disconnected,
+    DarkActive,
+    LightActive,
+}
+
+// The connected icon has to contrast with the panel: the light icon when
+// the desktop theme is dark, the dark icon when it is light
+fn active_icon_for_theme(is_dark: bool) -> IconState {
+    if is_dark {
+        IconState::LightActive
+    } else {
+        IconState::DarkActive
+    }
+}
+
+impl IconState {
+    // An unreadable status shows the inactive icon so the problem stays
+    // visible
+    fn for_status(status: WarpStatus, is_dark: bool) -> Self {
+        match status {
+            WarpStatus::Connecting => IconState::Connecting,
+            WarpStatus::Connected | WarpStatus::CaptivePortal => active_icon_for_theme(is_dark),
+            WarpStatus::Disconnected
+            | WarpStatus::UnableToConnect
+            | WarpStatus::RegistrationMissing
+            | WarpStatus::DaemonNotRunning
+            | WarpStatus::Unknown => IconState::Disconnected,
+        }
+    }
+}
+
+// Icons decoded once at startup; the poll loop only clones these
+struct TrayIcons {
+    connecting: Icon,
+    connecting_faded: Icon,
+    dark_active: Icon,
+    inactive: Icon,
+    light_active: Icon,
+    // Kept to composite badges onto
+    connecting_image: image::RgbaImage,
+    dark_active_image: image::RgbaImage,
+    inactive_image: image::RgbaImage,
+    light_active_image: image::RgbaImage,
+}
+
+impl TrayIcons {
+    fn load(app_icons: &AppIcons, paths: &IconPaths, size: Option<u32>) -> Self {
+        let active = paths.active.as_deref();
+        let connecting_image = load_icon_image(
+            paths.connecting.as_deref(),
+            app_icons.cloudflare_connecting,
+            size,
+            None,
+        );
+        let dark_active_image = load_icon_image(
+            paths.dark_active.as_deref().or(active),
+            app_icons.cloudflare_dark_active,
+            size,
+            Some(DARK_ICON_COLOR),
+        );
+        let inactive_image = load_icon_image(
+            paths.inactive.as_deref(),
+            app_icons.cloudflare_inactive,
+            size,
+            None,
+        );
+        let light_active_image = load_icon_image(
+            paths.light_active.as_deref().or(active),
+            app_icons.cloudflare_light_active,
+            size,
+            Some(LIGHT_ICON_COLOR),
+        );
+        TrayIcons {
+            connecting: tray_icon_from_rgba(connecting_image.clone()),
+            connecting_faded: faded_tray_icon(connecting_image.clone()),
+            dark_active: tray_icon_from_rgba(dark_active_image.clone()),
+            inactive: tray_icon_from_rgba(inactive_image.clone()),
+            light_active: tray_icon_from_rgba(light_active_image.clone()),
+            connecting_image,
+            dark_active_image,
+            inactive_image,
+            light_active_image,
+        }
+    }
+
+    fn with_badge(&self, state: IconState, badge: Badge) -> Icon {
+        let mut image = match state {
+            IconState::Connecting => self.connecting_image.clone(),
+            IconState::Disconnected => self.inactive_image.clone(),
+            IconState::DarkActive => self.dark_active_image.clone(),
+            IconState::LightActive => self.light_active_image.clone(),
+        };
+        overlay::draw(&mut image, badge);
+        tray_icon_from_rgba(image)
+    }
+
+    fn for_state(&self, state: IconState) -> &Icon {
+        match state {
+            IconState::Connecting => &self.connecting,
+            IconState::Disconnected => &self.inactive,
+            IconState::DarkActive => &self.dark_active,
+            IconState::LightActive => &self.light_active,
+        }
+    }
+}
+
+// Where the tray's icons come from, for each status and desktop theme. The
+// provider also draws the badge and the faded connecting frame, since only
+// it knows which images it holds.
+trait IconProvider {
+    fn icon(&self, status: WarpStatus, is_dark: bool) -> Icon;
+    fn badged_icon(&self, status: WarpStatus, is_dark: bool, badge: Badge) -> Icon;
+    fn faded_connecting_icon(&self, is_dark: bool) -> Icon;
+}
+
+impl IconProvider for TrayIcons {
+    fn icon(&self, status: WarpStatus, is_dark: bool) -> Icon {
+        self.for_state(IconState::for_status(status, is_dark))
+            .clone()
+    }
+
+    fn badged_icon(&self, status: WarpStatus, is_dark: bool, badge: Badge) -> Icon {
+        self.with_badge(IconState::for_status(status, is_dark), badge)
+    }
+
+    fn faded_connecting_icon(&self, _is_dark: bool) -> Icon {
+        self.connecting_faded.clone()
+    }
+}
+
+const ICON_STATUSES: [WarpStatus; 8] = [
+    WarpStatus::Connected,
+    WarpStatus::CaptivePortal,
+    WarpStatus::Connecting,
+    WarpStatus::Disconnected,
+    WarpStatus::UnableToConnect,
+    WarpStatus::RegistrationMissing,
+    WarpStatus::DaemonNotRunning,
+    WarpStatus::Unknown,
+];
+
+const ICON_EXTENSIONS: [&str; 3] = ["svg", "png", "ico"];
+
+fn icon_file_stem(status: WarpStatus) -> &'static str {
+    match status {
+        WarpStatus::Connected => "connected",
+        WarpStatus::CaptivePortal => "captive-portal",
+        WarpStatus::Connecting => "connecting",
+        WarpStatus::Disconnected => "disconnected",
+        WarpStatus::UnableToConnect => "unable-to-connect",
+        WarpStatus::RegistrationMissing => "registration-missing",
+        WarpStatus::DaemonNotRunning => "daemon-not-running",
+        WarpStatus::Unknown => "unknown",
+    }
+}
+
+// The files tried for a status, best match first: the variant for the
+// desktop theme (e.g. connected-dark.svg on a dark panel), then the plain one
+fn icon_file_candidates(dir: &Path, status: WarpStatus, is_dark: bool) -> Vec<PathBuf> {
+    let stem = icon_file_stem(status);
+    let theme = if is_dark { "dark" } else { "light" };
+    [format!("{}-{}", stem, theme), stem.to_string()]
+        .iter()
+        .flat_map(|name| {
+            ICON_EXTENSIONS
+                .iter()
+                .map(move |extension| dir.join(format!("{}.{}", name, extension)))
+        })
+        .collect()
+}
+
+// An icon set from a folder, one file per status. Statuses the folder has
+// no file for fall back to the other icons.
+struct DirectoryIcons {
+    images: HashMap<(WarpStatus, bool), image::RgbaImage>,
+    fallback: TrayIcons,
+}
+
+impl DirectoryIcons {
+    fn load(dir: &Path, size: Option<u32>, fallback: TrayIcons) -> Self {
+        let mut images = HashMap::new();
+        for status in ICON_STATUSES {
+            for is_dark in [false, true] {
+                let file = icon_file_candidates(dir, status, is_dark)
+                    .into_iter()
+                    .find_map(|path| fs::read(&path).ok().map(|bytes| (path, bytes)));
+                let Some((path, bytes)) = file else {
+                    continue;
+                };
+                match decode_icon(&bytes, size) {
+                    Ok(image) => {
+                        images.insert((status, is_dark), image);
+                    }
+                    Err(e) => warn!("Failed to decode icon {}: {}", path.display(), e),
+                }
+            }
+        }
+        if images.is_empty() {
+            warn!("No icons found in {}", dir.display());
+        }
+        DirectoryIcons { images, fallback }
+    }
+}
+
+impl IconProvider for DirectoryIcons {
+    fn icon(&self, status: WarpStatus, is_dark: bool) -> Icon {
+        match self.images.get(&(status, is_dark)) {
+            Some(image) => tray_icon_from_rgba(image.clone()),
+            None => self.fallback.icon(status, is_dark),
+        }
+    }
+
+    fn badged_icon(&self, status: WarpStatus, is_dark: bool, badge: Badge) -> Icon {
+        match self.images.get(&(status, is_dark)) {
+            Some(image) => {
+                let mut image = image.clone();
+                overlay::draw(&mut image, badge);
+                tray_icon_from_rgba(image)
+            }
+            None => self.fallback.badged_icon(status, is_dark, badge),
+        }
+    }
+
+    fn faded_connecting_icon(&self, is_dark: bool) -> Icon {
+        match self.images.get(&(WarpStatus::Connecting, is_dark)) {
+            Some(image) => faded_tray_icon(image.clone()),
+            None => self.fallback.faded_connecting_icon(is_dark),
+        }
+    }
+}
+
+// The tray icon, the menu items the poll timer keeps up to date and the
+// bookkeeping it needs between ticks
+struct Tray {
+    tray_icon: TrayIcon,
+    icons: Box<dyn IconProvider>,
+    theme: ThemeCache,
+    notifications: bool,
+    status: Option<WarpStatus>,
+    connected_since: Option<Instant>,
+    // The status and theme the current icon was picked for
+    shown_icon: Option<(WarpStatus, bool)>,
+    // Badge drawn on the connected icon, derived from the mode
+    show_badge: bool,
+    mode: Option<WarpMode>,
+    badge: Option<Badge>,
+    // Whether the connecting animation currently shows its faded frame
+    connecting_faded: bool,
+
+    menu: Menu,
+    menu_layout: MenuLayout,
+    current_status_item: MenuItem,
+    account_item: MenuItem,
+    // Inserted below the account while the device isn't registered
+    register_device_item: MenuItem,
+    register_device_shown: bool,
+    // Shown there too while warp-svc is down, if the config allows starting it
+    start_daemon_item: Option<MenuItem>,
+    start_daemon_shown: bool,
+    // Disabled line explaining an expired registration or license, shown
+    // there too while warp-cli reports one
+    expiry_item: MenuItem,
+    expiry_warning: Option<String>,
+    // "Via: SIN" below the account while connected, if warp-cli says
+    colo_item: MenuItem,
+    colo: Option<String>,
+    connect_item: MenuItem,
+    disconnect_item: MenuItem,
+    always_on_item: CheckMenuItem,
+    pause_item: MenuItem,
+    pause_label: String,
+    resume_item: MenuItem,
+    mode_items: Vec<(WarpMode, CheckMenuItem)>,
+    proxy_port_item: MenuItem,
+    current_mode_item: MenuItem,
+    dns_endpoint_item: MenuItem,
+    vnets_supported: bool,
+    vnet_menu: Submenu,
+    vnet_items: Vec<(String, CheckMenuItem)>,
+    vnets: Vec<VirtualNetwork>,
+    stats_menu: Submenu,
+    stats_items: Vec<MenuItem>,
+    refresh_rate_items: Vec<(Duration, CheckMenuItem)>,
+    account_checked_at: Option<Instant>,
+    settings_checked_at: Option<Instant>,
+    vnets_checked_at: Option<Instant>,
+    stats_checked_at: Option<Instant>,
+    tooltip: String,
+
+    // A passive read that dropped out of Connected and still waits for a
+    // second one to confirm it
+    pending_drop: bool,
+
+    // Read the status, account, settings, vnets and tunnel stats off the
+    // main loop
+    poller: BackgroundRead<PolledStatus>,
+    account_reader: BackgroundRead<Option<String>>,
+    settings_reader: BackgroundRead<Option<String>>,
+    vnet_reader: BackgroundRead<(Vec<VirtualNetwork>, Option<String>)>,
+    stats_reader: BackgroundRead<Vec<String>>,
+    // Pushes status changes when warp-cli supports --listen
+    listener: Option<StatusListener>,
+    // Set once a listener has reported a status, which shows that this
+    // warp-cli can listen and a stopped listener is worth restarting
+    listen_supported: bool,
+    refreshed_at: Option<Instant>,
+
+    // Back off while warp-cli keeps failing (daemon down, not installed)
+    interval: Duration,
+    failures: u32,
+    ticks_to_skip: u32,
+}
+
+impl Tray {
+    fn build(config: &Config, warp_cli: Option<&WarpCliInfo>) -> Self {
+        let warp_cli_found = warp_cli.is_some();
+        let supports = |min_version| warp_cli.is_some_and(|cli| cli.supports(min_version));
+
+        let tray_menu = Menu::new();
+        // Live status, refreshed by the poll timer
+        let current_status_item = MenuItem::new(
+            tr_args("menu-status", &[("status", &WarpStatus::Unknown.label())]),
+            false,
+            None,
+        );
+        let account_item = MenuItem::new(
+            tr_args("menu-account", &[("account", &tr("menu-account-unknown"))]),
+            false,
+            None,
+        );
+
+        // Ctrl+Shift plus a distinct letter for the common actions, Ctrl+Q for
+        // Quit. Windows and macOS show these in the tray menu and honor them
+        // while it is open; libappindicator menus on Linux ignore them.
+        let shortcut = |modifiers, code| Some(Accelerator::new(Some(modifiers), code));
+        let ctrl_shift = Modifiers::CONTROL | Modifiers::SHIFT;
+        let connect_item = MenuItem::with_id(
+            "connect",
+            tr("menu-connect"),
+            true,
+            shortcut(ctrl_shift, Code::KeyC),
+        );
+        let disconnect_item = MenuItem::with_id(
+            "disconnect",
+            tr("menu-disconnect"),
+            true,
+            shortcut(ctrl_shift, Code::KeyD),
+        );
+        let reconnect_item = MenuItem::with_id(
+            "reconnect",
+            tr("menu-reconnect"),
+            true,
+            shortcut(ctrl_shift, Code::KeyR),
+        );
+        let register_device_item =
+            MenuItem::with_id("register_device", tr("menu-register-device"), true, None);
+        let start_daemon_item = config
+            .start_daemon
+            .then(|| MenuItem::with_id("start_daemon", tr("menu-start-daemon"), true, None));
+        let status_item = MenuItem::with_id(
+            "status",
+            tr("menu-status-command"),
+            true,
+            shortcut(ctrl_shift, Code::KeyS),
+        );
+        let refresh_item = MenuItem::with_id("refresh", tr("menu-refresh"), true, None);
+        let check_connectivity_item = MenuItem::with_id(
+            "check_connectivity",
+            tr("menu-check-connectivity"),
+            true,
+            None,
+        );
+        // The pause item shows the time left while paused
+        let pause_label = tr_args(
+            "menu-pause",
+            &[("duration", &format_minutes(pause_duration(config)))],
+        );
+        let pause_item = MenuItem::with_id("pause", &pause_label, true, None);
+        let resume_item = MenuItem::with_id("resume", tr("menu-resume"), false, None);
+
+        // The check items and the lines below follow the settings once
+        // they are read, see apply_settings
+
+        // Always-On toggle; unchecked when the setting can't be read
+        let always_on_item =
+            CheckMenuItem::with_id("always_on", tr("menu-always-on"), true, false, None);
+
+        // Set mode options, except proxy mode, which goes in its own submenu;
+        // the active one is ticked by the poll timer
+        let mode_items: Vec<(WarpMode, CheckMenuItem)> = WarpMode::ALL
+            .into_iter()
+            .map(|mode| {
+                let label = mode.menu_label(config.menu.layout);
+                (
+                    mode,
+                    CheckMenuItem::with_id(mode.menu_id(), label, true, false, None),
+                )
+            })
+            .collect();
+        // The active mode at a glance, kept in step with the ticks
+        let current_mode_item = MenuItem::new(current_mode_label(None), false, None);
+
+        // Proxy mode and its port, as far as settings show it
+        let proxy_menu = Submenu::with_id("proxy", tr("menu-proxy"), true);
+        let proxy_port_item = MenuItem::new(proxy_port_label(None), false, None);
+        proxy_menu.append(&proxy_port_item).unwrap();
+        proxy_menu.append(&PredefinedMenuItem::separator()).unwrap();
+        for (mode, item) in &mode_items {
+            if *mode == WarpMode::Proxy {
+                proxy_menu.append(item).unwrap();
+            }
+        }
+        for port in PROXY_PORTS {
+            let id = format!("{}{}", PROXY_PORT_ID_PREFIX, port);
+            let label = tr_args("menu-use-proxy-port", &[("port", &port.to_string())]);
+            proxy_menu
+                .append(&MenuItem::with_id(id, label, true, None))
+                .unwrap();
+        }
+
+        // DNS families as offered by the installed warp-cli
+        let dns_families = if config.menu.dns_families && supports(DNS_FAMILIES_MIN_VERSION) {
+            read_dns_families(&SystemRunner)
+        } else {
+            Vec::new()
+        };
+        let dns_menu = Submenu::with_id("dns", tr("menu-dns-families"), true);
+        for family in &dns_families {
+            let id = format!("{}{}", DNS_FAMILY_ID_PREFIX, family);
+            dns_menu
+                .append(&MenuItem::with_id(id, family, true, None))
+                .unwrap();
+        }
+
+        // The custom DNS endpoint, as the settings show it
+        let dns_endpoint_menu =
+            Submenu::with_id("dns_endpoint", tr("menu-dns-endpoint-menu"), true);
+        let dns_endpoint_item = MenuItem::new(dns_endpoint_label(None), false, None);
+        dns_endpoint_menu.append(&dns_endpoint_item).unwrap();
+        dns_endpoint_menu
+            .append(&PredefinedMenuItem::separator())
+            .unwrap();
+        dns_endpoint_menu
+            .append(&MenuItem::with_id(
+                "set_dns_endpoint",
+                tr("menu-set-dns-endpoint"),
+                true,
+                None,
+            ))
+            .unwrap();
+        dns_endpoint_menu
+            .append(&MenuItem::with_id(
+                "clear_dns_endpoint",
+                tr("menu-clear-dns-endpoint"),
+                true,
+                None,
+            ))
+            .unwrap();
+
+        // Commands from the config file, each as given there
+        let custom_menu = Submenu::with_id("custom", tr("menu-custom"), true);
+        for (index, custom) in config.custom_commands.iter().enumerate() {
+            let id = format!("{}{}", CUSTOM_COMMAND_ID_PREFIX, index);
+            custom_menu
+                .append(&MenuItem::with_id(id, &custom.label, true, None))
+                .unwrap();
+        }
+
+        // Virtual networks are only known at runtime; the poll timer fills this in
+        let vnet_menu = Submenu::with_id("vnet", tr("menu-virtual-network"), false);
+
+        // Filled in from `warp-cli tunnel stats` while connected
+        let stats_menu = Submenu::with_id("stats", tr("menu-statistics"), false);
+
+        // Other warp-cli commands, each labelled with the command it runs
+        let other_label = match config.menu.layout {
+            MenuLayout::Flat => "menu-other",
+            MenuLayout::Nested => "menu-other-command",
+        };
+        let other = |id: &str, command: &str| {
+            MenuItem::with_id(
+                id,
+                tr_args(other_label, &[("command", command)]),
+                true,
+                None,
+            )
+        };
+        let teams_unenroll_item = other("teams_unenroll", "teams-unenroll");
+        let register_item = other("register", "register");
+        let enable_logging_item = other("enable_logging", "enable-logging");
+        let disable_logging_item = other("disable_logging", "disable-logging");
+        let trace_support_item = other("trace_support", "trace-support");
+        let generate_report_item = other("generate_report", "generate-report");
+        let about_item = MenuItem::with_id("about", tr("menu-about"), true, None);
+        // How often the status is polled; the current rate is ticked
+        let refresh_rate_menu = Submenu::with_id("refresh_rate", tr("menu-refresh-rate"), true);
+        let refresh_rate_items: Vec<(Duration, CheckMenuItem)> = POLL_PRESETS
+            .into_iter()
+            .map(|secs| {
+                let id = format!("{}{}", POLL_SECS_ID_PREFIX, secs);
+                let label = tr_args("menu-refresh-rate-secs", &[("secs", &secs.to_string())]);
+                let interval = Duration::from_secs(secs);
+                let checked = interval == poll_interval(config);
+                (
+                    interval,
+                    CheckMenuItem::with_id(id, label, true, checked, None),
+                )
+            })
+            .collect();
+        for (_, item) in &refresh_rate_items {
+            refresh_rate_menu.append(item).unwrap();
+        }
+        let open_warp_dir_item =
+            MenuItem::with_id("open_warp_dir", tr("menu-open-warp-dir"), true, None);
+        let copy_diagnostics_item =
+            MenuItem::with_id("copy_diagnostics", tr("menu-copy-diagnostics"), true, None);
+        let quit_item = MenuItem::with_id(
+            "quit",
+            tr("menu-quit"),
+            true,
+            shortcut(Modifiers::CONTROL, Code::KeyQ),
+        );
+
+        // Append all items to the tray menu. Groups left out in the config
+        // are still built, but never shown, so their events can't arrive.
+        // Both layouts hold the same items, so menu events are the same too.
+        let nested = config.menu.layout == MenuLayout::Nested;
+        let append_group = |id: &str, label: String, items: &[&dyn IsMenuItem]| {
+            if nested {
+                let submenu = Submenu::with_id(id, label, true);
+                submenu.append_items(items).unwrap();
+                tray_menu.append(&submenu).unwrap();
+            } else {
+                tray_menu.append_items(items).unwrap();
+            }
+        };
+        tray_menu.append(&current_status_item).unwrap();
+        tray_menu.append(&account_item).unwrap();
+        tray_menu.append(&PredefinedMenuItem::separator()).unwrap();
+        tray_menu.append(&connect_item).unwrap();
+        tray_menu.append(&disconnect_item).unwrap();
+        tray_menu.append(&reconnect_item).unwrap();
+        tray_menu.append(&pause_item).unwrap();
+        tray_menu.append(&resume_item).unwrap();
+        tray_menu.append(&status_item).unwrap();
+        tray_menu.append(&refresh_item).unwrap();
+        tray_menu.append(&check_connectivity_item).unwrap();
+        if nested {
+            tray_menu.append(&PredefinedMenuItem::separator()).unwrap();
+        }
+        append_group("startup", tr("menu-on-startup"), &[&always_on_item]);
+        if config.menu.modes {
+            tray_menu.append(&current_mode_item).unwrap();
+            let mut items: Vec<&dyn IsMenuItem> = mode_items
+                .iter()
+                .filter(|(mode, _)| *mode != WarpMode::Proxy)
+                .map(|(_, item)| item as &dyn IsMenuItem)
+                .collect();
+            items.push(&proxy_menu);
+            append_group("set_mode", tr("menu-set-mode-menu"), &items);
+        }
+        if !dns_families.is_empty() {
+            tray_menu.append(&dns_menu).unwrap();
+        }
+        tray_menu.append(&dns_endpoint_menu).unwrap();
+        let vnets_supported = supports(VNET_MIN_VERSION);
+        if vnets_supported {
+            tray_menu.append(&vnet_menu).unwrap();
+        }
+        tray_menu.append(&stats_menu).unwrap();
+        if !config.custom_commands.is_empty() {
+            tray_menu.append(&custom_menu).unwrap();
+        }
+        let separator = PredefinedMenuItem::separator();
+        let mut other_items: Vec<&dyn IsMenuItem> = Vec::new();
+        if config.menu.other {
+            other_items.extend([
+                &teams_unenroll_item as &dyn IsMenuItem,
+                &register_item,
+                &generate_report_item,
+            ]);
+        }
+        if config.menu.logging {
+            if nested && !other_items.is_empty() {
+                other_items.push(&separator);
+            }
+            other_items.extend([
+                &enable_logging_item as &dyn IsMenuItem,
+                &disable_logging_item,
+                &trace_support_item,
+            ]);
+        }
+        if !other_items.is_empty() {
+            append_group("other", tr("menu-other-menu"), &other_items);
+        }
+        tray_menu.append(&refresh_rate_menu).unwrap();
+        tray_menu.append(&open_warp_dir_item).unwrap();
+        tray_menu.append(&copy_diagnostics_item).unwrap();
+        tray_menu.append(&about_item).unwrap();
+        tray_menu.append(&PredefinedMenuItem::separator()).unwrap();
+        tray_menu.append(&quit_item).unwrap();
+
+        // Without warp-cli only Quit stays usable
+        if !warp_cli_found {
+            current_status_item.set_text(tr("menu-warp-cli-not-found"));
+            for item in [
+                &connect_item,
+                &disconnect_item,
+                &reconnect_item,
+                &pause_item,
+                &status_item,
+                &refresh_item,
+                &check_connectivity_item,
+                &teams_unenroll_item,
+                &register_item,
+                &enable_logging_item,
+                &disable_logging_item,
+                &trace_support_item,
+                &generate_report_item,
+            ] {
+                item.set_enabled(false);
+            }
+            always_on_item.set_enabled(false);
+            custom_menu.set_enabled(false);
+            proxy_menu.set_enabled(false);
+            dns_endpoint_menu.set_enabled(false);
+            for (_, item) in &mode_items {
+                item.set_enabled(false);
+            }
+        }
+
+        let size = icon_size(config);
+        let tray_icons = TrayIcons::load(bundled_icons(config.icon_style), &config.icons, size);
+        let icons: Box<dyn IconProvider> = match &config.icons.dir {
+            Some(dir) => Box::new(DirectoryIcons::load(dir, size, tray_icons)),
+            None => Box::new(tray_icons),
+        };
+
+        // Build the tray icon with the menu and initial icon.
+        let tooltip = if warp_cli_found {
+            tr("tooltip-default")
+        } else {
+            tr("tooltip-warp-cli-not-found")
+        };
+        let tray_icon =
+            build_tray_icon(&tray_menu, &tooltip, icons.icon(WarpStatus::Unknown, false))
+                .expect("Failed to build tray icon");
+
+        Tray {
+            tray_icon,
+            icons,
+            theme: ThemeCache::new(config.theme),
+            notifications: config.notifications,
+            status: None,
+            connected_since: None,
+            shown_icon: None,
+            show_badge: config.badge,
+            mode: None,
+            badge: None,
+            connecting_faded: false,
+            menu: tray_menu,
+            menu_layout: config.menu.layout,
+            current_status_item,
+            account_item,
+            register_device_item,
+            register_device_shown: false,
+            start_daemon_item,
+            start_daemon_shown: false,
+            expiry_item: MenuItem::new("", false, None),
+            expiry_warning: None,
+            colo_item: MenuItem::new("", false, None),
+            colo: None,
+            connect_item,
+            disconnect_item,
+            always_on_item,
+            pause_item,
+            pause_label,
+            resume_item,
+            mode_items,
+            proxy_port_item,
+            current_mode_item,
+            dns_endpoint_item,
+            vnets_supported,
+            vnet_menu,
+            vnet_items: Vec::new(),
+            vnets: Vec::new(),
+            stats_menu,
+            stats_items: Vec::new(),
+            refresh_rate_items,
+            account_checked_at: None,
+            settings_checked_at: None,
+            vnets_checked_at: None,
+            stats_checked_at: None,
+            tooltip,
+            pending_drop: false,
+            poller: BackgroundRead::new(),
+            account_reader: BackgroundRead::new(),
+            settings_reader: BackgroundRead::new(),
+            vnet_reader: BackgroundRead::new(),
+            stats_reader: BackgroundRead::new(),
+            listener: warp_cli.and_then(|_| StatusListener::spawn(&SystemRunner)),
+            listen_supported: false,
+            refreshed_at: None,
+            interval: poll_interval(config),
+            failures: 0,
+            ticks_to_skip: 0,
+        }
+    }
+
+    // One poll timer tick. While the status listener runs, status changes
+    // arrive as events and the timer only refreshes the rest now and then.
+    fn tick(&mut self) {
+        self.restart_listener();
+        self.update_pause();
+        self.update_status_item();
+        if self.ticks_to_skip > 0 {
+            self.ticks_to_skip -= 1;
+            return;
+        }
+        if self.listener.as_ref().is_some_and(StatusListener::is_alive)
+            && self
+                .refreshed_at
+                .is_some_and(|at| at.elapsed() < LISTEN_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.poll_status(true);
+    }
+
+    // The status is applied once the read finishes, by handle_status_events
+    fn poll_status(&mut self, passive: bool) {
+        self.poller.start(move || PolledStatus {
+            status: read_warp_status(&SystemRunner),
+            passive,
+        });
+    }
+
+    // Apply a polled status, backing off while warp-cli fails
+    fn apply_polled(&mut self, polled: PolledStatus) {
+        let PolledStatus { status, passive } = polled;
+        if status.is_unreadable() {
+            self.failures += 1;
+            self.ticks_to_skip = backoff_ticks(self.interval, self.failures);
+        } else {
+            self.failures = 0;
+        }
+
+        // A single non-connected read right after Connected is often just
+        // warp-svc restarting; only a second one in a row changes the icon.
+        // A read that was asked for is shown as it is.
+        if passive
+            && self.status.is_some_and(WarpStatus::is_connected)
+            && !status.is_connected()
+            && !self.pending_drop
+        {
+            debug!(
+                "Status {:?} after Connected, waiting for the next read",
+                status
+            );
+            self.pending_drop = true;
+            return;
+        }
+        self.update(status);
+    }
+
+    // Backoff is counted in ticks, so it starts over at the new rate
+    fn set_interval(&mut self, interval: Duration) {
+        info!("Polling every {}", humantime::format_duration(interval));
+        self.interval = interval;
+        self.failures = 0;
+        self.ticks_to_skip = 0;
+        for (item_interval, item) in &self.refresh_rate_items {
+            item.set_checked(*item_interval == interval);
+        }
+    }
+
+    // Count down a pause in the menu and reconnect once it is over
+    fn update_pause(&mut self) {
+        let remaining = paused_until().map(|until| until.saturating_duration_since(Instant::now()));
+        if remaining == Some(Duration::ZERO) {
+            info!("Pause is over, reconnecting");
+            set_paused_until(None);
+            std::thread::spawn(|| resume(&SystemRunner));
+        }
+        match remaining.filter(|remaining| !remaining.is_zero()) {
+            Some(remaining) => {
+                self.pause_item.set_text(tr_args(
+                    "menu-paused",
+                    &[("remaining", &format_minutes(remaining))],
+                ));
+                self.pause_item.set_enabled(false);
+                self.resume_item.set_enabled(true);
+            }
+            None => {
+                self.pause_item.set_text(&self.pause_label);
+                self.pause_item.set_enabled(true);
+                self.resume_item.set_enabled(false);
+            }
+        }
+    }
+
+    // Polling takes over while the listener is down, until it comes back
+    fn restart_listener(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        if !self.listen_supported
+            || listener.is_alive()
+            || listener.started_at.elapsed() < LISTENER_RESTART_INTERVAL
+        {
+            return;
+        }
+        info!("Restarting the warp-cli status listener");
+        // The stopped one stays when spawning fails, to be retried next tick
+        if let Some(listener) = StatusListener::spawn(&SystemRunner) {
+            self.listener = Some(listener);
+        }
+    }
+
+    // Apply the newest status from the listener or a finished read, if any
+    // arrived, and poll right away when Refresh was picked. A Refresh during
+    // a read waits for it to finish, then gets a read of its own.
+    fn handle_status_events(&mut self) {
+        if let Some(status) = self.listener.as_ref().and_then(StatusListener::latest) {
+            self.listen_supported = true;
+            self.update(status);
+        }
+        if let Some(polled) = self.poller.latest() {
+            self.apply_polled(polled);
+        }
+        if let Some(output) = self.account_reader.latest() {
+            self.update_account(output.as_deref());
+        }
+        if let Some(settings) = self.settings_reader.latest() {
+            self.apply_settings(settings.as_deref().unwrap_or_default());
+        }
+        if let Some((vnets, selected_vnet)) = self.vnet_reader.latest() {
+            self.update_vnets(vnets, selected_vnet.as_deref());
+        }
+        self.refresh_stats();
+        if !self.poller.is_busy() && REFRESH_REQUESTED.swap(false, Ordering::SeqCst) {
+            self.update_pause();
+            self.update_status_item();
+            self.poll_status(false);
+        }
+    }
+
+    // Bring the menu and icon in line with `status`, however it was read. A
+    // notification is shown when the status changes, except for the first
+    // read and the transient Connecting state.
+    fn update(&mut self, status: WarpStatus) {
+        self.refreshed_at = Some(Instant::now());
+        self.pending_drop = false;
+        if !status.is_connected() {
+            self.connected_since = None;
+        } else if !self.status.is_some_and(WarpStatus::is_connected) {
+            self.connected_since = Some(Instant::now());
+        }
+        // Both stay enabled when the status is unknown; neither is any use
+        // before the device is registered or without warp-svc
+        let unregistered = status == WarpStatus::RegistrationMissing;
+        let daemon_down = status == WarpStatus::DaemonNotRunning;
+        self.connect_item
+            .set_enabled(!status.is_connected() && !unregistered && !daemon_down);
+        self.disconnect_item
+            .set_enabled(status != WarpStatus::Disconnected && !unregistered && !daemon_down);
+        if unregistered != self.register_device_shown {
+            // Right below the status and account lines
+            let result = if unregistered {
+                self.menu.insert(&self.register_device_item, 2)
+            } else {
+                self.menu.remove(&self.register_device_item)
+            };
+            if let Err(e) = result {
+                warn!("Failed to update the Register item: {}", e);
+            }
+            self.register_device_shown = unregistered;
+        }
+        if let Some(item) = &self.start_daemon_item {
+            if daemon_down != self.start_daemon_shown {
+                let result = if daemon_down {
+                    self.menu.insert(item, 2)
+                } else {
+                    self.menu.remove(item)
+                };
+                if let Err(e) = result {
+                    warn!("Failed to update the Start Service item: {}", e);
+                }
+                self.start_daemon_shown = daemon_down;
+            }
+        }
+
+        // The account rarely changes, so it is read less often
+        if self
+            .account_checked_at
+            .is_none_or(|at| at.elapsed() >= ACCOUNT_REFRESH_INTERVAL)
+        {
+            self.account_reader
+                .start(|| read_warp_account_output(&SystemRunner));
+            self.account_checked_at = Some(Instant::now());
+        }
+
+        // Tunnel statistics only exist while connected; they are read again
+        // as soon as it connects
+        if !status.is_connected() {
+            self.update_stats(&[]);
+            self.show_colo(None);
+            self.stats_checked_at = None;
+        }
+
+        // Re-read now and then so changes made elsewhere show up too. The
+        // vnets also follow the status, since connecting can change them.
+        let settings_changed = SETTINGS_CHANGED.swap(false, Ordering::SeqCst);
+        if self.vnets_supported
+            && (settings_changed
+                || self.status != Some(status)
+                || self
+                    .vnets_checked_at
+                    .is_none_or(|at| at.elapsed() >= SETTINGS_REFRESH_INTERVAL))
+        {
+            self.vnet_reader.start(|| read_warp_vnets(&SystemRunner));
+            self.vnets_checked_at = Some(Instant::now());
+        }
+        if settings_changed
+            || self
+                .settings_checked_at
+                .is_none_or(|at| at.elapsed() >= SETTINGS_REFRESH_INTERVAL)
+        {
+            self.settings_reader
+                .start(|| read_warp_settings(&SystemRunner));
+            self.settings_checked_at = Some(Instant::now());
+        }
+
+        if self.notifications {
+            self.notify_status_change(status);
+        }
+        self.status = Some(status);
+        WARP_CONNECTED.store(status == WarpStatus::Connected, Ordering::SeqCst);
+        self.update_tooltip();
+        self.update_status_item();
+        self.apply_icon();
+    }
+
+    // Called before self.status moves on to `status`. A portal gets a
+    // sign-in prompt, on the first read too, since nothing gets through until
+    // the user signs in.
+    fn notify_status_change(&self, status: WarpStatus) {
+        if status == WarpStatus::CaptivePortal && self.status != Some(status) {
+            notify(
+                &tr("notify-captive-portal"),
+                &tr("notify-captive-portal-body"),
+            );
+        } else if self.status.is_some_and(|last| last != status) && status != WarpStatus::Connecting
+        {
+            let body = tr_args("tooltip-status", &[("status", &status.label())]);
+            // A drop gets a button to bring WARP back up
+            if status == WarpStatus::Disconnected
+                && self.status.is_some_and(WarpStatus::is_connected)
+            {
+                notify_disconnected(body);
+            } else {
+                notify("Cloudflare WARP", &body);
+            }
+        }
+    }
+
+    // Check items, locks and the lines showing the mode, proxy port and DNS
+    // endpoint, from `warp-cli settings`. Empty when they can't be read.
+    fn apply_settings(&mut self, settings: &str) {
+        let always_on_managed = is_managed(settings, "Always On");
+        self.always_on_item
+            .set_checked(parse_always_on(settings) == Some(true));
+        self.always_on_item
+            .set_text(managed_label(tr("menu-always-on"), always_on_managed));
+        self.always_on_item.set_enabled(!always_on_managed);
+        let mode = parse_warp_mode(settings);
+        let mode_managed = is_managed(settings, "Mode");
+        for (item_mode, item) in &self.mode_items {
+            item.set_checked(mode == Some(*item_mode));
+            item.set_text(managed_label(
+                item_mode.menu_label(self.menu_layout),
+                mode_managed,
+            ));
+            item.set_enabled(!mode_managed);
+        }
+        self.current_mode_item.set_text(current_mode_label(mode));
+        self.dns_endpoint_item
+            .set_text(dns_endpoint_label(parse_dns_endpoint(settings)));
+        self.proxy_port_item
+            .set_text(proxy_port_label(parse_proxy_port(settings)));
+        self.mode = mode;
+        self.update_tooltip();
+        self.apply_icon();
+    }
+
+    fn update_tooltip(&mut self) {
+        let Some(status) = self.status else {
+            return;
+        };
+        let tooltip = match self.mode {
+            Some(mode) => tr_args(
+                "tooltip-status-mode",
+                &[("status", &status.label()), ("mode", mode.arg())],
+            ),
+            None => tr_args("tooltip-status", &[("status", &status.label())]),
+        };
+        if tooltip != self.tooltip {
+            let _ = self.tray_icon.set_tooltip(Some(&tooltip));
+            self.tooltip = tooltip;
+        }
+    }
+
+    // The uptime is counted from the first read that found WARP connected
+    fn update_status_item(&self) {
+        let Some(status) = self.status else {
+            return;
+        };
+        let label = match self.connected_since {
+            Some(since) => tr_args(
+                "menu-status-uptime",
+                &[
+                    ("status", &status.label()),
+                    ("uptime", &format_uptime(since.elapsed())),
+                ],
+            ),
+            None => tr_args("menu-status", &[("status", &status.label())]),
+        };
+        self.current_status_item.set_text(label);
+    }
+
+    // The account line and any expiry warning, from `warp-cli account`
+    fn update_account(&mut self, output: Option<&str>) {
+        let account = output.and_then(parse_account);
+        let account = account.unwrap_or_else(|| tr("menu-account-unknown"));
+        self.account_item
+            .set_text(tr_args("menu-account", &[("account", &account)]));
+        let warning = output.and_then(parse_expiry_warning);
+        self.show_expiry_warning(warning.map(str::to_string));
+    }
+
+    // Start a stats read when one is due and show the last one that finished.
+    // A read that finished after a disconnect is out of date.
+    fn refresh_stats(&mut self) {
+        let connected = self.status.is_some_and(WarpStatus::is_connected);
+        if connected
+            && self
+                .stats_checked_at
+                .is_none_or(|at| at.elapsed() >= STATS_REFRESH_INTERVAL)
+        {
+            self.stats_reader.start(|| read_tunnel_stats(&SystemRunner));
+            self.stats_checked_at = Some(Instant::now());
+        }
+        if let Some(stats) = self.stats_reader.latest() {
+            if connected {
+                self.update_stats(&stats);
+                self.show_colo(parse_colo(&stats).map(str::to_string));
+            }
+        }
+    }
+
+    // Counters change on every read, so relabel items in place and only
+    // rebuild when the number of lines changes
+    fn update_stats(&mut self, stats: &[String]) {
+        if stats.len() == self.stats_items.len() {
+            for (item, label) in self.stats_items.iter().zip(stats) {
+                item.set_text(label);
+            }
+            return;
+        }
+
+        while self.stats_menu.remove_at(0).is_some() {}
+        self.stats_items = stats
+            .iter()
+            .map(|label| {
+                let item = MenuItem::new(label, false, None);
+                self.stats_menu.append(&item).unwrap();
+                item
+            })
+            .collect();
+        self.stats_menu.set_enabled(!self.stats_items.is_empty());
+    }
+
+    // Rebuild the vnet submenu only when the list itself changes
+    fn update_vnets(&mut self, vnets: Vec<VirtualNetwork>, selected: Option<&str>) {
+        if vnets != self.vnets {
+            while self.vnet_menu.remove_at(0).is_some() {}
+            self.vnet_items = vnets
+                .iter()
+                .map(|vnet| {
+                    let id = format!("{}{}", VNET_ID_PREFIX, vnet.id);
+                    let item = CheckMenuItem::with_id(id, &vnet.name, true, false, None);
+                    self.vnet_menu.append(&item).unwrap();
+                    (vnet.id.clone(), item)
+                })
+                .collect();
+            self.vnet_menu.set_enabled(!vnets.is_empty());
+            self.vnets = vnets;
+        }
+        for (id, item) in &self.vnet_items {
+            item.set_checked(selected == Some(id.as_str()));
+        }
+    }
+
+    fn show_colo(&mut self, colo: Option<String>) {
+        if colo == self.colo {
+            return;
+        }
+        if let Some(colo) = &colo {
+            self.colo_item
+                .set_text(tr_args("menu-colo", &[("colo", colo)]));
+        }
+        let result = match (&self.colo, &colo) {
+            (None, Some(_)) => self.menu.insert(&self.colo_item, 2),
+            (Some(_), None) => self.menu.remove(&self.colo_item),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to update the data center line: {}", e);
+        }
+        self.colo = colo;
+    }
+
+    fn show_expiry_warning(&mut self, warning: Option<String>) {
+        if warning == self.expiry_warning {
+            return;
+        }
+        if let Some(warning) = &warning {
+            warn!("warp-cli reports: {}", warning);
+            self.expiry_item
+                .set_text(tr_args("menu-expiry-warning", &[("reason", warning)]));
+        }
+        let result = match (&self.expiry_warning, &warning) {
+            // Right below the status and account lines
+            (None, Some(_)) => self.menu.insert(&self.expiry_item, 2),
+            (Some(_), None) => self.menu.remove(&self.expiry_item),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to update the expiry warning: {}", e);
+        }
+        self.expiry_warning = warning;
+        self.apply_icon();
+    }
+
+    // The icon is only replaced when its state changes, to avoid flicker
+    fn apply_icon(&mut self) {
+        let Some(status) = self.status else {
+            return;
+        };
+        let is_dark = self.theme.is_dark();
+        // A warning outranks the mode, whatever the status
+        let badge = match (self.show_badge, status, self.mode) {
+            _ if self.expiry_warning.is_some() => Some(Badge::Warning),
+            (_, WarpStatus::CaptivePortal, _) => Some(Badge::Warning),
+            (true, WarpStatus::Connected, Some(mode)) => Some(mode.badge()),
+            _ => None,
+        };
+        if self.shown_icon != Some((status, is_dark)) || self.badge != badge {
+            let icon = match badge {
+                Some(badge) => self.icons.badged_icon(status, is_dark, badge),
+                None => self.icons.icon(status, is_dark),
+            };
+            let _ = self.tray_icon.set_icon(Some(icon));
+            self.shown_icon = Some((status, is_dark));
+            self.badge = badge;
+            self.connecting_faded = false;
+        }
+    }
+
+    fn is_connecting(&self) -> bool {
+        matches!(self.shown_icon, Some((WarpStatus::Connecting, _)))
+    }
+
+    // Swap to the other connecting frame. Returns false once the status has
+    // moved on, which ends the animation.
+    fn next_connecting_frame(&mut self) -> bool {
+        let Some((WarpStatus::Connecting, is_dark)) = self.shown_icon else {
+            return false;
+        };
+        self.connecting_faded = !self.connecting_faded;
+        let icon = if self.connecting_faded {
+            self.icons.faded_connecting_icon(is_dark)
+        } else {
+            self.icons.icon(WarpStatus::Connecting, is_dark)
+        };
+        let _ = self.tray_icon.set_icon(Some(icon));
+        true
+    }
+
+    // For event loops without timers: shows the next connecting frame when
+    // it is due and returns when the one after that is
+    #[cfg(any(windows, target_os = "macos"))]
+    fn animate(&mut self, next_frame: Option<Instant>) -> Option<Instant> {
+        if !self.is_connecting() {
+            return None;
+        }
+        match next_frame {
+            Some(at) if Instant::now() < at => Some(at),
+            Some(_) => {
+                self.next_connecting_frame();
+                Some(Instant::now() + CONNECTING_FRAME_INTERVAL)
+            }
+            None => Some(Instant::now() + CONNECTING_FRAME_INTERVAL),
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn refresh_theme(&mut self) {
+        self.theme.refresh();
+        self.apply_icon();
+    }
+
+    // A new icon with the same menu, tooltip and image, for a tray host that
+    // came back without picking up the old one
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn rebuild_icon(&mut self) {
+        info!("Re-creating the tray icon");
+        let icon = self.icons.icon(WarpStatus::Unknown, false);
+        match build_tray_icon(&self.menu, &self.tooltip, icon) {
+            Ok(tray_icon) => {
+                self.tray_icon = tray_icon;
+                self.shown_icon = None;
+                self.apply_icon();
+            }
+            Err(e) => error!("Failed to re-create the tray icon: {}", e),
+        }
+    }
+}
+
+fn build_tray_icon(menu: &Menu, tooltip: &str, icon: Icon) -> tray_icon::Result<TrayIcon> {
+    TrayIconBuilder::new()
+        .with_menu(Box::new(menu.clone()))
+        .with_menu_on_left_click(false)
+        .with_tooltip(tooltip)
+        .with_icon(icon)
+        .build()
+}
+
+// Set by Quit; the event threads stop listening and the main loop exits
+static QUIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// How long the event threads wait for an event before checking for Quit
+const EVENT_WAIT: Duration = Duration::from_millis(250);
+
+fn spawn_event_threads(config: &Config) -> Vec<JoinHandle<()>> {
+    let config = config.clone();
+    vec![
+        std::thread::spawn(move || handle_menu_events(&config)),
+        std::thread::spawn(handle_tray_events),
+    ]
+}
+
+// Called once the main loop has exited. A thread may still be waiting on a
+// warp-cli command, which is allowed to finish; one that is parked between
+// checks is woken up.
+fn join_event_threads(threads: Vec<JoinHandle<()>>) {
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+    for thread in threads {
+        thread.thread().unpark();
+        if thread.join().is_err() {
+            error!("An event thread panicked");
+        }
+    }
+}
+
+// A panic in one handler would otherwise end its event thread and leave the
+// menu dead without a word. The panic is logged and the loop goes on; if the
+// event channel itself is gone, the next receive reports it and stops.
+fn catch_handler_panic(event: &str, handler: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(handler)) {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        error!("Handling {} panicked: {}", event, message);
+    }
+}
+
+// A repeat of an action this soon after it finished is taken as an impatient
+// extra click that queued up while the first one ran
+const REPEAT_CLICK_WINDOW: Duration = Duration::from_secs(1);
+
+// When each action last finished, or None while it is running
+static ACTIONS: LazyLock<Mutex<HashMap<String, Option<Instant>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Held for as long as an action runs, so the same warp-cli command isn't
+// started twice side by side
+struct ActionGuard(String);
+
+impl ActionGuard {
+    fn acquire(action: &str) -> Option<Self> {
+        let mut actions = ACTIONS.lock().unwrap();
+        match actions.get(action) {
+            Some(None) => return None,
+            Some(Some(finished)) if finished.elapsed() < REPEAT_CLICK_WINDOW => return None,
+            _ => {}
+        }
+        actions.insert(action.to_string(), None);
+        Some(ActionGuard(action.to_string()))
+    }
+}
+
+impl Drop for ActionGuard {
+    fn drop(&mut self) {
+        ACTIONS
+            .lock()
+            .unwrap()
+            .insert(self.0.clone(), Some(Instant::now()));
+    }
+}
+
+// Runs on its own thread; menu clicks block on warp-cli, not the UI
+fn handle_menu_events(config: &Config) {
+    while !QUIT_REQUESTED.load(Ordering::SeqCst) {
+        match MenuEvent::receiver().recv_timeout(EVENT_WAIT) {
+            Ok(event) => {
+                let Some(guard) = ActionGuard::acquire(&event.id.0) else {
+                    debug!("Ignoring a repeated click on {}", event.id.0);
+                    continue;
+                };
+                catch_handler_panic(&event.id.0, || {
+                    dispatch_menu_event(&event.id.0, &SystemRunner, config, guard)
+                });
+            }
+            Err(e) if e.is_timeout() => {}
+            Err(e) => {
+                error!("Error receiving menu event: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+// What each menu item does. The guard is held until the action is done,
+// which for the actions that wait is on a thread of their own.
+fn dispatch_menu_event(id: &str, runner: &dyn CommandRunner, config: &Config, guard: ActionGuard) {
+    match id {
+        // Basic operations
+        // Connecting by hand ends a pause early
+        "connect" => {
+            set_paused_until(None);
+            let _ = run_warp_command(runner, "connect", &[]);
+        }
+        "disconnect" => {
+            let _ = run_warp_command(runner, "disconnect", &[]);
+        }
+        // Waits for the disconnect, so it must not hold up the menu
+        "reconnect" => {
+            std::thread::spawn(move || {
+                let _guard = guard;
+                reconnect(&SystemRunner);
+            });
+        }
+        "pause" => pause(runner, pause_duration(config)),
+        "resume" => resume(runner),
+        "status" => {
+            let _ = run_warp_command(runner, "status", &[]);
+        }
+        // Settings are re-read along with the status
+        "refresh" => {
+            SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+            request_refresh();
+        }
+        // trace-support takes a few seconds
+        "check_connectivity" => {
+            std::thread::spawn(move || {
+                let _guard = guard;
+                check_connectivity(&SystemRunner);
+            });
+        }
+
+        // Startup options
+        "always_on" => {
+            // Toggle based on the daemon's state, not the check mark
+            let settings = read_warp_settings(runner).unwrap_or_default();
+            let command = if parse_always_on(&settings) == Some(true) {
+                "disable-always-on"
+            } else {
+                "enable-always-on"
+            };
+            let _ = run_warp_command(runner, command, &[]);
+            SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+        }
+
+        // Mode options
+        id if id.starts_with("set_mode_") => {
+            match WarpMode::ALL.into_iter().find(|mode| mode.menu_id() == id) {
+                Some(mode) => set_mode(
+                    runner,
+                    mode,
+                    config.restore_mode || config.reapply_on_upgrade,
+                    config.connect_after_mode_change,
+                ),
+                None => warn!("Unknown mode item {}", id),
+            }
+        }
+
+        id if id.starts_with(POLL_SECS_ID_PREFIX) => {
+            match id[POLL_SECS_ID_PREFIX.len()..].parse::<u64>() {
+                Ok(secs) if POLL_PRESETS.contains(&secs) => set_refresh_rate(secs),
+                _ => warn!("Invalid refresh rate in {}", id),
+            }
+        }
+
+        id if id.starts_with(PROXY_PORT_ID_PREFIX) => {
+            match parse_port(&id[PROXY_PORT_ID_PREFIX.len()..]) {
+                Some(port) => {
+                    let _ = run_warp_command(runner, "set-proxy-port", &[&port.to_string()]);
+                    SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+                }
+                None => warn!("Invalid proxy port in {}", id),
+            }
+        }
+
+        id if id.starts_with(DNS_FAMILY_ID_PREFIX) => {
+            let _ = run_warp_command(
+                runner,
+                "dns",
+                &["families", &id[DNS_FAMILY_ID_PREFIX.len()..]],
+            );
+        }
+
+        "set_dns_endpoint" => set_dns_endpoint(runner),
+        // Only in the menu when the config allows it
+        "start_daemon" if config.start_daemon => start_daemon(runner),
+        "clear_dns_endpoint" => {
+            let _ = run_warp_command(runner, "clear-gateway", &[]);
+            SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+        }
+
+        id if id.starts_with(VNET_ID_PREFIX) => {
+            let _ = run_warp_command(runner, "vnet", &[&id[VNET_ID_PREFIX.len()..]]);
+            SETTINGS_CHANGED.store(true, Ordering::SeqCst);
+        }
+
+        id if id.starts_with(CUSTOM_COMMAND_ID_PREFIX) => {
+            let custom = id[CUSTOM_COMMAND_ID_PREFIX.len()..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| config.custom_commands.get(index));
+            let args: Vec<&str> = custom
+                .map(|custom| custom.args.iter().map(String::as_str).collect())
+                .unwrap_or_default();
+            match args.split_first() {
+                Some((command, args)) => {
+                    let _ = run_warp_command(runner, command, args);
+                }
+                None => warn!("Custom command {} has no args", id),
+            }
+        }
+
+        // Other options
+        "teams_unenroll" => {
+            let _ = run_warp_command(runner, "teams-unenroll", &[]);
+        }
+        "register" | "register_device" => {
+            let _ = run_warp_command(runner, "register", &[]);
+        }
+        "enable_logging" => {
+            let _ = run_warp_command(runner, "enable-logging", &[]);
+        }
+        "disable_logging" => {
+            let _ = run_warp_command(runner, "disable-logging", &[]);
+        }
+        "trace_support" => {
+            let _ = run_warp_command(runner, "trace-support", &[]);
+        }
+        "generate_report" => generate_report(runner),
+        "open_warp_dir" => open_warp_dir(runner, &warp_dir(config)),
+        "copy_diagnostics" => {
+            let redact = config.redact.clone();
+            std::thread::spawn(move || {
+                let _guard = guard;
+                copy_diagnostics(&SystemRunner, &redact);
+            });
+        }
+        "about" => show_about(),
+
+        "quit" => {
+            info!("Quitting");
+            request_quit();
+        }
+        _ => {}
+    }
+}
+
+// A left click on the icon toggles the connection; the menu stays on right
+// click. libappindicator doesn't report clicks, so this never fires on Linux.
+fn handle_tray_events() {
+    let runner = SystemRunner;
+    while !QUIT_REQUESTED.load(Ordering::SeqCst) {
+        match TrayIconEvent::receiver().recv_timeout(EVENT_WAIT) {
+            Ok(TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            }) => catch_handler_panic("left click", || {
+                let command = match read_warp_status(&runner) {
+                    WarpStatus::Connected | WarpStatus::CaptivePortal | WarpStatus::Connecting => {
+                        "disconnect"
+                    }
+                    WarpStatus::RegistrationMissing => "register",
+                    _ => "connect",
+                };
+                // Shares the menu's guard, so a click and a menu pick don't race
+                let Some(_guard) = ActionGuard::acquire(command) else {
+                    debug!("Ignoring a repeated click on the icon");
+                    return;
+                };
+                let _ = run_warp_command(&runner, command, &[]);
+            }),
+            Ok(_) => {}
+            Err(e) if e.is_timeout() => {}
+            Err(e) => {
+                error!("Error receiving tray event: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // RUST_LOG=debug traces every warp-cli call
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let cli = Cli::parse();
+    if let Some(path) = &cli.warp_cli_path {
+        let _ = WARP_CLI_PATH.set(path.into());
+    }
+
+    if let Some(command) = &cli.command {
+        return match command {
+            cli::Command::Status { json } => print_status(*json),
+            #[cfg(target_os = "linux")]
+            cli::Command::InstallService => setup_exit_code(autostart::install_service()),
+            #[cfg(target_os = "linux")]
+            cli::Command::UninstallService => setup_exit_code(autostart::uninstall_service()),
+            #[cfg(target_os = "linux")]
+            cli::Command::EnableAutostart => setup_exit_code(autostart::enable_autostart()),
+            #[cfg(target_os = "linux")]
+            cli::Command::DisableAutostart => setup_exit_code(autostart::disable_autostart()),
+        };
+    }
+
+    // Status bar integrations run once and exit without a tray
+    if cli.i3blocks {
+        print_i3blocks_status();
+        return ExitCode::SUCCESS;
+    }
+
+    let (mut config, config_error) = config::load();
+    if let Some(e) = config_error {
+        warn!("Ignoring invalid config {}", e);
+        notify(
+            &tr("notify-config-ignored"),
+            &format!("{}\n\n{}", e, tr("notify-config-ignored-body")),
+        );
+    }
+    cli.apply(&mut config);
+    config.apply_icon_theme(IconTheme::from_env());
+    run(&config)
+}
+
+#[cfg(target_os = "linux")]
+fn setup_exit_code(result: io::Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("warp-taskbar: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// gtk::main_quit must run on the main thread
+#[cfg(all(unix, not(target_os = "macos")))]
+fn request_quit() {
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+    glib::idle_add_once(gtk::main_quit);
+}
+
+// Set while a wake-up is queued, so a burst of events only queues one
+#[cfg(all(unix, not(target_os = "macos")))]
+static WAKE_QUEUED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(all(unix, not(target_os = "macos")))]
+thread_local! {
+    // What the main loop does once woken up, set by run()
+    static ON_WAKE: RefCell<Option<Box<dyn Fn()>>> = const { RefCell::new(None) };
+}
+
+// Called from any thread once something arrived for the main loop: a
+// status, a finished read, a Refresh or a new refresh rate
+#[cfg(all(unix, not(target_os = "macos")))]
+fn wake_main_loop() {
+    if WAKE_QUEUED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    glib::idle_add_once(|| {
+        WAKE_QUEUED.store(false, Ordering::SeqCst);
+        ON_WAKE.with(|on_wake| {
+            if let Some(on_wake) = &*on_wake.borrow() {
+                on_wake();
+            }
+        });
+    });
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn run(config: &Config) -> ExitCode {
+    // Initialize GTK (needed on Linux)
+    // Usually no display to connect to. Without GTK there is no dialog to
+    // show, but a notification may still get through to the user.
+    if let Err(e) = gtk::init() {
+        error!("Failed to initialize GTK: {}", e);
+        notify(
+            &tr("notify-start-failed"),
+            &tr_args("notify-gtk-failed", &[("error", &e.to_string())]),
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let warp_cli = detect_warp_cli(&SystemRunner);
+    let warp_cli_found = warp_cli.is_some();
+    if let Some(warp_cli) = warp_cli {
+        apply_saved_settings(&SystemRunner, warp_cli, config);
+    }
+    let tray = Rc::new(RefCell::new(Tray::build(config, warp_cli)));
+
+    let mut threads = spawn_event_threads(config);
+    if warp_cli_found && config.connectivity_probe {
+        threads.push(spawn_connectivity_probe());
+    }
+
+    // systemctl --user stop sends SIGTERM; take the same path as Quit
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        glib::unix_signal_add_local(signal, move || {
+            info!("Received signal {}, quitting", signal);
+            request_quit();
+            glib::ControlFlow::Continue
+        });
+    }
+
+    // A tray host that restarts (GNOME Shell with an AppIndicator extension,
+    // a new panel) doesn't always pick the icon up again, so it is re-created
+    // whenever the StatusNotifierWatcher comes back. SIGUSR1 does the same on
+    // demand.
+    let host_lost = Arc::new(AtomicBool::new(false));
+    let host_returned = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let (lost, returned) = (host_lost.clone(), host_returned.clone());
+        gtk::gio::bus_watch_name(
+            gtk::gio::BusType::Session,
+            "org.kde.StatusNotifierWatcher",
+            gtk::gio::BusNameWatcherFlags::NONE,
+            move |_, _, _| {
+                if lost.swap(false, Ordering::SeqCst) {
+                    returned.store(true, Ordering::SeqCst);
+                    wake_main_loop();
+                }
+            },
+            move |_, _| host_lost.store(true, Ordering::SeqCst),
+        )
+    };
+    let signal_tray = tray.clone();
+    let rebuild_signal = glib::unix_signal_add_local(libc::SIGUSR1, move || {
+        signal_tray.borrow_mut().rebuild_icon();
+        glib::ControlFlow::Continue
+    });
+
+    // There is nothing to poll without warp-cli
+    let poll_timer = Rc::new(RefCell::new(
+        warp_cli_found.then(|| start_poll_timer(&tray, poll_interval(config))),
+    ));
+
+    // Picks up status events, finished reads, tray host changes and refresh
+    // rates picked from the menu, which arrive off the main loop and wake it
+    // up. A status that moved to Connecting starts the animation.
+    let event_tray = tray.clone();
+    let event_poll_timer = poll_timer.clone();
+    let animating = Rc::new(Cell::new(false));
+    let handle_events = move || {
+        event_tray.borrow_mut().handle_status_events();
+        if event_tray.borrow().is_connecting() && !animating.get() {
+            start_connecting_animation(&event_tray, &animating);
+        }
+        if host_returned.swap(false, Ordering::SeqCst) {
+            event_tray.borrow_mut().rebuild_icon();
+        }
+        if let Some(interval) = take_poll_interval_request() {
+            event_tray.borrow_mut().set_interval(interval);
+            let mut poll_timer = event_poll_timer.borrow_mut();
+            if let Some(source) = poll_timer.take() {
+                source.remove();
+                *poll_timer = Some(start_poll_timer(&event_tray, interval));
+            }
+        }
+    };
+    ON_WAKE.with(|on_wake| *on_wake.borrow_mut() = Some(Box::new(handle_events)));
+
+    let mut theme_handlers = Vec::new();
+    let settings = gtk::Settings::default();
+    if warp_cli_found {
+        // Re-detect the theme as soon as GTK reports a change instead of
+        // waiting for the cached value to expire.
+        if let Some(settings) = &settings {
+            let theme_tray = tray.clone();
+            theme_handlers.push(settings.connect_gtk_application_prefer_dark_theme_notify(
+                move |_| theme_tray.borrow_mut().refresh_theme(),
+            ));
+            let theme_tray = tray.clone();
+            theme_handlers.push(
+                settings.connect_gtk_theme_name_notify(move |_| {
+                    theme_tray.borrow_mut().refresh_theme()
+                }),
+            );
+        }
+    }
+
+    // Start the GTK main loop.
+    gtk::main();
+
+    // Drop every reference to the tray so its icon is removed
+    let poll_timer = poll_timer.borrow_mut().take();
+    for source in poll_timer.into_iter().chain([rebuild_signal]) {
+        source.remove();
+    }
+    ON_WAKE.with(|on_wake| on_wake.borrow_mut().take());
+    gtk::gio::bus_unwatch_name(watcher);
+    if let Some(settings) = &settings {
+        for handler in theme_handlers {
+            settings.disconnect(handler);
+        }
+    }
+    join_event_threads(threads);
+    ExitCode::SUCCESS
+}
+
+// The status poll; its reads are applied once they wake the main loop
+#[cfg(all(unix, not(target_os = "macos")))]
+fn start_poll_timer(tray: &Rc<RefCell<Tray>>, interval: Duration) -> glib::SourceId {
+    let tray = tray.clone();
+    glib::timeout_add_local(interval, move || {
+        tray.borrow_mut().tick();
+        glib::ControlFlow::Continue
+    })
+}
+
+// A separate timer from the status poll: it only swaps icon frames and
+// removes itself once a poll has moved the status past Connecting
+#[cfg(all(unix, not(target_os = "macos")))]
+fn start_connecting_animation(tray: &Rc<RefCell<Tray>>, animating: &Rc<Cell<bool>>) {
+    animating.set(true);
+    let tray = tray.clone();
+    let animating = animating.clone();
+    glib::timeout_add_local(CONNECTING_FRAME_INTERVAL, move || {
+        if tray.borrow_mut().next_connecting_frame() {
+            glib::ControlFlow::Continue
+        } else {
+            animating.set(false);
+            glib::ControlFlow::Break
+        }
+    });
+}
+
+// Thread running the win32 message loop, so other threads can post WM_QUIT
+#[cfg(windows)]
+static MAIN_THREAD_ID: OnceLock<u32> = OnceLock::new();
+
+#[cfg(windows)]
+fn request_quit() {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT};
+
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+    if let Some(thread_id) = MAIN_THREAD_ID.get() {
+        unsafe {
+            PostThreadMessageW(*thread_id, WM_QUIT, 0, 0);
+        }
+    }
+}
+
+// Other threads wake the message loop with an empty message
+#[cfg(windows)]
+fn wake_main_loop() {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{PostThreadMessageW, WM_NULL};
+
+    if let Some(thread_id) = MAIN_THREAD_ID.get() {
+        unsafe {
+            PostThreadMessageW(*thread_id, WM_NULL, 0, 0);
+        }
+    }
+}
+
+// tray-icon needs a win32 message loop on the thread that created the icon.
+// Wait for either a message or the next poll tick, whichever comes first;
+// other threads post one when they have something for it.
+#[cfg(windows)]
+fn run(config: &Config) -> ExitCode {
+    use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, MsgWaitForMultipleObjects, PeekMessageW, TranslateMessage, MSG,
+        PM_REMOVE, QS_ALLINPUT, WM_QUIT,
+    };
+
+    let _ = MAIN_THREAD_ID.set(unsafe { GetCurrentThreadId() });
+
+    let warp_cli = detect_warp_cli(&SystemRunner);
+    let warp_cli_found = warp_cli.is_some();
+    if let Some(warp_cli) = warp_cli {
+        apply_saved_settings(&SystemRunner, warp_cli, config);
+    }
+    let mut tray = Tray::build(config, warp_cli);
+
+    let mut threads = spawn_event_threads(config);
+    if warp_cli_found && config.connectivity_probe {
+        threads.push(spawn_connectivity_probe());
+    }
+
+    let mut interval = poll_interval(config);
+    let mut next_tick = Instant::now();
+    let mut next_frame = None;
+    'main: loop {
+        let wake_at = next_frame.map_or(next_tick, |frame: Instant| frame.min(next_tick));
+        let timeout = wake_at.saturating_duration_since(Instant::now());
+        let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        unsafe {
+            MsgWaitForMultipleObjects(0, std::ptr::null(), 0, timeout_ms, QS_ALLINPUT);
+        }
+
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { PeekMessageW(&mut msg, std::ptr::null_mut(), 0, 0, PM_REMOVE) } != 0 {
+            if msg.message == WM_QUIT {
+                break 'main;
+            }
+            unsafe {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+
+        if let Some(new_interval) = take_poll_interval_request() {
+            tray.set_interval(new_interval);
+            interval = new_interval;
+            next_tick = Instant::now() + interval;
+        }
+        if Instant::now() >= next_tick {
+            // There is nothing to poll without warp-cli
+            if warp_cli_found {
+                tray.tick();
+            }
+            next_tick = Instant::now() + interval;
+        }
+        tray.handle_status_events();
+        next_frame = tray.animate(next_frame);
+    }
+
+    join_event_threads(threads);
+    ExitCode::SUCCESS
+}
+
+#[cfg(target_os = "macos")]
+fn request_quit() {
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+    wake_main_loop();
+}
+
+// Set once the event loop runs, since NSApp may only be created on the main
+// thread
+#[cfg(target_os = "macos")]
+static EVENT_LOOP_STARTED: AtomicBool = AtomicBool::new(false);
+
+// Other threads wake the event loop with an application-defined event,
+// which AppKit allows them to post
+#[cfg(target_os = "macos")]
+fn wake_main_loop() {
+    use objc2_app_kit::{NSApplication, NSEvent, NSEventModifierFlags, NSEventType};
+    use objc2_foundation::{MainThreadMarker, NSPoint};
+
+    if !EVENT_LOOP_STARTED.load(Ordering::SeqCst) {
+        return;
+    }
+    let event = unsafe {
+        NSEvent::otherEventWithType_location_modifierFlags_timestamp_windowNumber_context_subtype_data1_data2(
+            NSEventType::ApplicationDefined,
+            NSPoint::new(0.0, 0.0),
+            NSEventModifierFlags::empty(),
+            0.0,
+            0,
+            None,
+            0,
+            0,
+            0,
+        )
+    };
+    if let Some(event) = event {
+        // NSApp already exists, and postEvent:atStart: may be called from
+        // any thread
+        let mtm = unsafe { MainThreadMarker::new_unchecked() };
+        NSApplication::sharedApplication(mtm).postEvent_atStart(&event, false);
+    }
+}
+
+// tray-icon needs the AppKit event loop running on the main thread. Pump
+// events until the next poll tick or until another thread posts one.
+#[cfg(target_os = "macos")]
+fn run(config: &Config) -> ExitCode {
+    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy, NSEventMask};
+    use objc2_foundation::{MainThreadMarker, NSDate, NSDefaultRunLoopMode};
+
+    let Some(mtm) = MainThreadMarker::new() else {
+        error!("The tray must be started from the main thread");
+        return ExitCode::FAILURE;
+    };
+    let app = NSApplication::sharedApplication(mtm);
+    // Status bar item only, no Dock icon
+    app.setActivationPolicy(NSApplicationActivationPolicy::Accessory);
+    unsafe { app.finishLaunching() };
+    EVENT_LOOP_STARTED.store(true, Ordering::SeqCst);
+
+    let warp_cli = detect_warp_cli(&SystemRunner);
+    let warp_cli_found = warp_cli.is_some();
+    if let Some(warp_cli) = warp_cli {
+        apply_saved_settings(&SystemRunner, warp_cli, config);
+    }
+    let mut tray = Tray::build(config, warp_cli);
+
+    let mut threads = spawn_event_threads(config);
+    if warp_cli_found && config.connectivity_probe {
+        threads.push(spawn_connectivity_probe());
+    }
+
+    let mut interval = poll_interval(config);
+    let mut next_tick = Instant::now();
+    let mut next_frame = None;
+    while !QUIT_REQUESTED.load(Ordering::SeqCst) {
+        let wake_at = next_frame.map_or(next_tick, |frame: Instant| frame.min(next_tick));
+        let timeout = wake_at.saturating_duration_since(Instant::now());
+        let until = unsafe { NSDate::dateWithTimeIntervalSinceNow(timeout.as_secs_f64()) };
+        let event = unsafe {
+            app.nextEventMatchingMask_untilDate_inMode_dequeue(
+                NSEventMask::Any,
+                Some(&until),
+                NSDefaultRunLoopMode,
+                true,
+            )
+        };
+        if let Some(event) = event {
+            unsafe { app.sendEvent(&event) };
+        }
+
+        if let Some(new_interval) = take_poll_interval_request() {
+            tray.set_interval(new_interval);
+            interval = new_interval;
+            next_tick = Instant::now() + interval;
+        }
+        if Instant::now() >= next_tick {
+            // There is nothing to poll without warp-cli
+            if warp_cli_found {
+                tray.tick();
+            }
+            next_tick = Instant::now() + interval;
+        }
+        tray.handle_status_events();
+        next_frame = tray.animate(next_frame);
+    }
+
+    join_event_threads(threads);
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner::MockRunner;
+
+    #[test]
+    fn parses_connected() {
+        let stdout = "Status update: Connected\nNetwork: healthy\n";
+        assert_eq!(parse_warp_status(stdout), WarpStatus::Connected);
+    }
+
+    #[test]
+    fn parses_connecting() {
+        let stdout =
+            "Status update: Connecting\nReason: Establishing connection to 162.159.192.1:2408\n";
+        assert_eq!(parse_warp_status(stdout), WarpStatus::Connecting);
+    }
+
+    #[test]
+    fn parses_captive_portal() {
+        let stdout = "Status update: Connected\nNetwork: Captive Portal detected\n";
+        assert_eq!(parse_warp_status(stdout), WarpStatus::CaptivePortal);
+        let stdout = "Status update: Connected\nNetwork: healthy\n";
+        assert_eq!(parse_warp_status(stdout), WarpStatus::Connected);
+    }
+
+    // What the listener sends for `lines` printed by warp-cli
+    fn forwarded_statuses(lines: &[&str]) -> Vec<WarpStatus> {
+        let (line_sender, line_receiver) = mpsc::channel();
+        for line in lines {
+            line_sender.send(line.to_string()).unwrap();
+        }
+        drop(line_sender);
+        let (sender, receiver) = mpsc::channel();
+        forward_statuses(line_receiver, sender);
+        receiver.try_iter().collect()
+    }
+
+    #[test]
+    fn listener_waits_for_the_line_after_an_update() {
+        assert_eq!(
+            forwarded_statuses(&[
+                "Status update: Connected",
+                "Network: Captive Portal detected",
+            ]),
+            [WarpStatus::CaptivePortal]
+        );
+        assert_eq!(
+            forwarded_statuses(&[
+                "Status update: Unable",
+                "Reason: Registration Missing",
+                "Status update: Connecting",
+                "Status update: Connected",
+                "Network: healthy",
+            ]),
+            [
+                WarpStatus::RegistrationMissing,
+                WarpStatus::Connecting,
+                WarpStatus::Connected
+            ]
+        );
+        assert_eq!(
+            forwarded_statuses(&["Status update: Disconnected"]),
+            [WarpStatus::Disconnected]
+        );
+    }
+
+    #[test]
+    fn detects_a_stopped_daemon() {
+        let stderr = "Error: Unable to connect to the CloudflareWARP daemon. \
+                      Maybe the daemon is not running?\n";
+        assert!(daemon_not_running(stderr));
+        assert!(!daemon_not_running("Error: Registration Missing\n"));
+    }
+
+    #[test]
+    fn parses_disconnected() {
+        let stdout = "Status update: Disconnected\nReason: Manual Disconnection\n";
+        assert_eq!(parse_warp_status(stdout), WarpStatus::Disconnected);
+    }
+
+    #[test]
+    fn parses_unable_to_connect() {
+        for stdout in [
+            "Status update: Unable to connect\n",
+            "Status update: Unable\nReason: Happy Eyeballs failed\n",
+        ] {
+            assert_eq!(parse_warp_status(stdout), WarpStatus::UnableToConnect);
+        }
+    }
+
+    #[test]
+    fn parses_registration_missing() {
+        for stdout in [
+            "Status update: Unable\nReason: Registration Missing due to: Daemon Startup\n",
+            // As the status listener sees it
+            "Reason: Registration Missing due to: Daemon Startup",
+        ] {
+            assert_eq!(parse_warp_status(stdout), WarpStatus::RegistrationMissing);
+        }
+    }
+
+    #[test]
+    fn policy_settings_are_managed() {
+        let settings = "Merged configuration:\n\
+                        (local policy)\tMode: WarpWithDnsOverHttps\n\
+                        (user set)\tAlways On: true\n";
+        assert!(is_managed(settings, "Mode"));
+        assert!(!is_managed(settings, "Always On"));
+        assert!(!is_managed("Always On: true\n", "Always On"));
+        assert_eq!(parse_warp_mode(settings), Some(WarpMode::WarpDoh));
+    }
+
+    #[test]
+    fn gateway_ids_are_validated() {
+        for input in [
+            "abc123",
+            " ABC123.cloudflare-gateway.com ",
+            "https://abc123.cloudflare-gateway.com/dns-query",
+        ] {
+            assert_eq!(parse_gateway_id(input).as_deref(), Some("abc123"));
+        }
+        for input in ["", "https://", "-abc", "abc 123", "dns.google/x?y"] {
+            assert_eq!(parse_gateway_id(input), None, "{}", input);
+        }
+    }
+
+    #[test]
+    fn reads_the_colo_from_tunnel_stats() {
+        let stats = parse_tunnel_stats(
+            "Tunnel Protocol: WireGuard\nEndpoints: 162.159.192.1:2408\nColo: SIN\n",
+        );
+        assert_eq!(parse_colo(&stats), Some("SIN"));
+        let stats = parse_tunnel_stats("Endpoints: 162.159.192.1:2408\nSent: 1MB; Received: 2MB\n");
+        assert_eq!(parse_colo(&stats), None);
+    }
+
+    #[test]
+    fn parses_proxy_mode_and_port() {
+        let settings = "(user set)\tMode: WarpProxy on port 40000\n";
+        assert_eq!(parse_warp_mode(settings), Some(WarpMode::Proxy));
+        assert_eq!(parse_proxy_port(settings), Some(40000));
+        assert_eq!(parse_proxy_port("Mode: Warp\n"), None);
+    }
+
+    #[test]
+    fn proxy_ports_must_be_in_range() {
+        assert_eq!(parse_port("1080"), Some(1080));
+        assert_eq!(parse_port("0"), None);
+        assert_eq!(parse_port("65536"), None);
+        assert_eq!(parse_port("-1"), None);
+    }
+
+    #[test]
+    fn finds_expiry_warnings() {
+        let output = "Account type: Free\nError: Your registration has expired\n";
+        assert_eq!(
+            parse_expiry_warning(output),
+            Some("Error: Your registration has expired")
+        );
+        // A license key alone is no warning
+        let output = "Account type: Limited\nLicense: 0a1B2c3D-4e5F6g7H-8i9J0k1L\n";
+        assert_eq!(parse_expiry_warning(output), None);
+        assert_eq!(parse_expiry_warning(""), None);
+    }
+
+    #[test]
+    fn expiry_dates_and_names_are_no_warning() {
+        let output = "Account type: Team
+Key expiry: 2027-01-01
+Device name: clocktower
+";
+        assert_eq!(parse_expiry_warning(output), None);
+        let output = "Error: System clock skew too large
+";
+        assert_eq!(
+            parse_expiry_warning(output),
+            Some("Error: System clock skew too large")
+        );
+    }
+
+    #[test]
+    fn reads_warp_from_the_trace() {
+        let trace = "fl=123f4\nip=203.0.113.7\nwarp=on\ngateway=off\n";
+        assert_eq!(parse_trace_warp(trace), Some("on"));
+        assert_eq!(parse_trace_warp("warp=off\n"), Some("off"));
+        assert_eq!(parse_trace_warp("Error: not connected\n"), None);
+    }
+
+    #[test]
+    fn garbage_is_unknown() {
+        for stdout in [
+            "",
+            "garbage",
+            "Error: Daemon not running\n",
+            "Status update:\n",
+        ] {
+            assert_eq!(parse_warp_status(stdout), WarpStatus::Unknown);
+        }
+    }
+
+    fn warp_cli_status() -> String {
+        format!("{} status", warp_cli_path().to_string_lossy())
+    }
+
+    #[test]
+    fn reads_status_through_the_runner() {
+        let runner = MockRunner::new().with(&warp_cli_status(), 0, "Status update: Disconnected\n");
+        assert_eq!(read_warp_status(&runner), WarpStatus::Disconnected);
+    }
+
+    #[test]
+    fn missing_warp_cli_is_unknown() {
+        assert_eq!(read_warp_status(&MockRunner::new()), WarpStatus::Unknown);
+    }
+
+    // A message missing from en.ftl would show up as its id
+    #[test]
+    fn every_message_is_in_the_english_file() {
+        let english: Vec<&str> = include_str!("../i18n/en.ftl")
+            .lines()
+            .filter_map(|line| line.split_once(" =").map(|(id, _)| id))
+            .collect();
+        let source = include_str!("main.rs");
+        // Spelled out in pieces so this test doesn't find itself
+        for call in [concat!("tr", "("), concat!("tr_args", "(")] {
+            for rest in source.split(call).skip(1) {
+                let Some(rest) = rest.trim_start().strip_prefix('"') else {
+                    continue;
+                };
+                let id = rest.split('"').next().unwrap();
+                assert!(english.contains(&id), "{} is missing from en.ftl", id);
+            }
+        }
+    }
+
+    #[test]
+    fn the_service_is_started_through_the_runner() {
+        let runner = MockRunner::new().with(&START_DAEMON_COMMAND.join(" "), 0, "");
+        assert_eq!(run_start_daemon_command(&runner), Ok(()));
+        assert!(run_start_daemon_command(&MockRunner::new()).is_err());
+    }
+
+    #[test]
+    fn only_global_ipv6_addresses_count() {
+        assert!(is_global_ipv6("2606:4700:4700::1111".parse().unwrap()));
+        assert!(!is_global_ipv6("fe80::1".parse().unwrap()));
+        assert!(!is_global_ipv6("fd00::1".parse().unwrap()));
+        assert!(!is_global_ipv6(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn i3blocks_colors_tell_statuses_apart() {
+        let mut colors: Vec<&str> = ICON_STATUSES
+            .iter()
+            .map(|&status| i3blocks_short_text_and_color(status).1)
+            .collect();
+        colors.sort();
+        colors.dedup();
+        assert_eq!(colors.len(), ICON_STATUSES.len());
+    }
+
+    #[test]
+    fn upgrades_are_version_changes_after_the_first_start() {
+        assert!(warp_cli_upgraded(Some("2024.6.415"), "2025.1.861"));
+        assert!(!warp_cli_upgraded(Some("2025.1.861"), "2025.1.861"));
+        assert!(!warp_cli_upgraded(None, "2025.1.861"));
+    }
+
+    #[test]
+    fn diagnostics_include_every_command() {
+        let warp_cli = warp_cli_path().to_string_lossy().into_owned();
+        let runner = MockRunner::new()
+            .with(&warp_cli_status(), 0, "Status update: Connected\n")
+            .with(
+                &format!("{} --version", warp_cli),
+                0,
+                "warp-cli 2024.6.415.0\n",
+            );
+        let text = collect_diagnostics(&runner, &Redact::default());
+        assert!(text.contains("== warp-cli status ==\nStatus update: Connected\n"));
+        assert!(text.contains("== warp-cli settings ==\nFailed to run: "));
+        assert!(text.contains("== warp-cli --version ==\nwarp-cli 2024.6.415.0\n"));
+    }
+
+    #[test]
+    fn diagnostics_leave_out_ids() {
+        let text = "(user set)\tDevice ID: 0f3c1a2b-4d5e-6f70-8192-a3b4c5d6e7f8\n\
+                    Account ID: 1234\n\
+                    Registration 0f3c1a2b-4d5e-6f70-8192-a3b4c5d6e7f8 is valid\n\
+                    Mode: Warp\n";
+        assert_eq!(
+            redact_diagnostics(text, &Redact::default()),
+            "(user set)\tDevice ID: ***\nAccount ID: ***\nRegistration *** is valid\nMode: Warp\n"
+        );
+    }
+
+    #[test]
+    fn diagnostics_leave_out_identity_fields() {
+        let text = "Organization: Example Corp\n\
+                    Device name: alice-laptop\n\
+                    Contact admin@example.com, or try 198.51.100.7:443.\n\
+                    Excluded: 10.0.0.0/8 and 2001:db8::1\n\
+                    warp-cli 2024.6.415.0\n";
+        assert_eq!(
+            redact_diagnostics(text, &Redact::default()),
+            "Organization: ***\n\
+             Device name: ***\n\
+             Contact ***, or try ***.\n\
+             Excluded: *** and ***\n\
+             warp-cli 2024.6.415.0\n"
+        );
+        let keep_all = Redact {
+            organization: false,
+            email: false,
+            ip_addresses: false,
+            device_name: false,
+        };
+        assert_eq!(redact_diagnostics(text, &keep_all), text);
+    }
+
+    #[test]
+    fn themed_icon_files_come_first() {
+        let dir = Path::new("/icons");
+        let candidates = icon_file_candidates(dir, WarpStatus::UnableToConnect, true);
+        assert_eq!(candidates.len(), 6);
+        assert_eq!(candidates[0], dir.join("unable-to-connect-dark.svg"));
+        assert_eq!(candidates[2], dir.join("unable-to-connect-dark.ico"));
+        assert_eq!(candidates[3], dir.join("unable-to-connect.svg"));
+    }
+
+    #[test]
+    fn malformed_icons_fall_back_to_bundled() {
+        assert!(decode_icon(b"not an image", Some(16)).is_err());
+        let path =
+            env::temp_dir().join(format!("warp-taskbar-bad-icon-{}.png", std::process::id()));
+        fs::write(&path, b"not an image").unwrap();
+        let image = load_icon_image(Some(&path), APP_ICONS.cloudflare_inactive, Some(16), None);
+        let _ = fs::remove_file(&path);
+        assert_eq!(image.dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn repeated_actions_are_dropped() {
+        let guard = ActionGuard::acquire("test_action");
+        assert!(guard.is_some());
+        assert!(ActionGuard::acquire("test_action").is_none());
+        assert!(ActionGuard::acquire("other_test_action").is_some());
+        drop(guard);
+        // Still inside the repeat window
+        assert!(ActionGuard::acquire("test_action").is_none());
+    }
+
+    // The menu handlers, run against a shell script standing in for warp-cli
+    #[cfg(unix)]
+    mod dispatch {
+        use super::*;
+        use config::CustomCommand;
+        use runner::FakeWarpCli;
+        use std::process;
+
+        // Keeps the handlers' notifications off the desktop and the commands
+        // they run out of the user's history
+        fn isolate() {
+            let _ = NOTIFIER.set(|_, _| {});
+            state::use_state_dir(
+                env::temp_dir().join(format!("warp-taskbar-test-state-{}", process::id())),
+            );
+        }
+
+        // Runs a menu item's handler against a fake warp-cli and returns the
+        // command lines it ran
+        fn dispatch(id: &str, config: &Config, stdout: &str) -> Vec<String> {
+            isolate();
+            let warp_cli = FakeWarpCli::install(warp_cli_path(), stdout);
+            let guard = ActionGuard::acquire(id).expect("Each test uses its own menu items");
+            dispatch_menu_event(id, &warp_cli, config, guard);
+            warp_cli.calls()
+        }
+
+        #[test]
+        fn menu_items_run_their_commands() {
+            let config = Config::default();
+            assert_eq!(dispatch("disconnect", &config, ""), ["disconnect"]);
+            assert_eq!(dispatch("set_mode_doh", &config, ""), ["set-mode doh"]);
+            assert_eq!(
+                dispatch("dns_family:malware", &config, ""),
+                ["dns families malware"]
+            );
+            assert_eq!(
+                dispatch("proxy_port:8080", &config, ""),
+                ["set-proxy-port 8080"]
+            );
+        }
+
+        #[test]
+        fn invalid_proxy_ports_run_nothing() {
+            let config = Config::default();
+            assert!(dispatch("proxy_port:0", &config, "").is_empty());
+            assert!(dispatch("proxy_port:70000", &config, "").is_empty());
+        }
+
+        #[test]
+        fn custom_commands_run_as_configured() {
+            let config = Config {
+                custom_commands: vec![CustomCommand {
+                    label: "Malware filtering".to_string(),
+                    args: vec![
+                        "dns".to_string(),
+                        "families".to_string(),
+                        "malware".to_string(),
+                    ],
+                }],
+                ..Config::default()
+            };
+            assert_eq!(
+                dispatch("custom_command:0", &config, ""),
+                ["dns families malware"]
+            );
+            assert!(dispatch("custom_command:1", &config, "").is_empty());
+        }
+
+        // Toggled from what the daemon reports, read through the same runner
+        #[test]
+        fn always_on_follows_the_settings() {
+            let config = Config::default();
+            assert_eq!(
+                dispatch("always_on", &config, "Always On: false\n"),
+                ["settings", "enable-always-on"]
+            );
+        }
+
+        #[test]
+        fn remembered_mode_is_read_back_through_the_runner() {
+            let config = Config {
+                restore_mode: true,
+                ..Config::default()
+            };
+            assert_eq!(
+                dispatch("set_mode_proxy", &config, "Mode: WarpProxy on port 40000\n"),
+                ["set-mode proxy", "settings"]
+            );
+        }
+
+        #[test]
+        fn mode_change_connects_when_disconnected() {
+            let config = Config {
+                connect_after_mode_change: true,
+                ..Config::default()
+            };
+            assert_eq!(
+                dispatch("set_mode_warp", &config, "Status update: Disconnected\n"),
+                ["set-mode warp", "status", "connect"]
+            );
+            assert_eq!(
+                dispatch("set_mode_dot", &config, "Status update: Connected\n"),
+                ["set-mode dot", "status"]
+            );
+        }
+
+        #[test]
+        fn listener_reports_captive_portals() {
+            isolate();
+            let warp_cli = FakeWarpCli::install(
+                warp_cli_path(),
+                "Status update: Connected\nNetwork: Captive Portal detected\n",
+            );
+            let listener = StatusListener::spawn(&warp_cli).expect("Failed to start the listener");
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while listener.is_alive() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(listener.latest(), Some(WarpStatus::CaptivePortal));
+            assert_eq!(warp_cli.calls(), ["--listen status"]);
+        }
+    }
+
+    #[test]
+    fn dark_theme_gets_the_light_icon() {
+        assert_eq!(active_icon_for_theme(true), IconState::LightActive);
+        assert_eq!(
+            IconState::for_status(WarpStatus::Connected, true),
+            IconState::LightActive
+        );
+    }
+
+    #[test]
+    fn light_theme_gets_the_dark_icon() {
+        assert_eq!(active_icon_for_theme(false), IconState::DarkActive);
+        assert_eq!(
+            IconState::for_status(WarpStatus::Connected, false),
+            IconState::DarkActive
+        );
+    }
 }
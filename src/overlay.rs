@@ -0,0 +1,80 @@
+use image::{imageops, Rgba, RgbaImage};
+
+// A small marker drawn in the bottom-right corner of the icon: the mode while
+// connected, or an amber "!" when something needs the user's attention
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Badge {
+    Dot,
+    Letter(char),
+    Warning,
+}
+
+const BADGE_COLOR: Rgba<u8> = Rgba([0x2E, 0xCC, 0x71, 0xFF]);
+const WARNING_COLOR: Rgba<u8> = Rgba([0xF3, 0x9C, 0x12, 0xFF]);
+const GLYPH_COLOR: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+
+// 3x5 glyphs, one row per byte with the leftmost pixel in bit 2
+fn glyph(letter: char) -> Option<[u8; 5]> {
+    match letter {
+        '!' => Some([0b010, 0b010, 0b010, 0b000, 0b010]),
+        'D' => Some([0b110, 0b101, 0b101, 0b101, 0b110]),
+        'P' => Some([0b110, 0b101, 0b110, 0b100, 0b100]),
+        'T' => Some([0b111, 0b010, 0b010, 0b010, 0b010]),
+        _ => None,
+    }
+}
+
+fn render(badge: Badge, diameter: u32) -> RgbaImage {
+    let (color, letter) = match badge {
+        Badge::Dot => (BADGE_COLOR, None),
+        Badge::Letter(letter) => (BADGE_COLOR, Some(letter)),
+        Badge::Warning => (WARNING_COLOR, Some('!')),
+    };
+    let mut image = RgbaImage::new(diameter, diameter);
+    let radius = diameter as f32 / 2.0;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        if dx * dx + dy * dy <= radius * radius {
+            *pixel = color;
+        }
+    }
+
+    let Some(rows) = letter.and_then(glyph) else {
+        return image;
+    };
+    // Leave at least a pixel of the circle around the glyph
+    let scale = (diameter.saturating_sub(2) / 5).max(1);
+    let left = diameter.saturating_sub(3 * scale) / 2;
+    let top = diameter.saturating_sub(5 * scale) / 2;
+    for (row, bits) in rows.iter().enumerate() {
+        for column in 0..3 {
+            if bits & (0b100 >> column) == 0 {
+                continue;
+            }
+            for sy in 0..scale {
+                for sx in 0..scale {
+                    let x = left + column * scale + sx;
+                    let y = top + row as u32 * scale + sy;
+                    if x < diameter && y < diameter {
+                        image.put_pixel(x, y, GLYPH_COLOR);
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+// The badge covers about two thirds of the icon's shorter side
+pub fn draw(icon: &mut RgbaImage, badge: Badge) {
+    let (width, height) = icon.dimensions();
+    let diameter = (width.min(height) * 2 / 3).max(7).min(width.min(height));
+    let badge = render(badge, diameter);
+    imageops::overlay(
+        icon,
+        &badge,
+        i64::from(width - diameter),
+        i64::from(height - diameter),
+    );
+}
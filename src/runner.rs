@@ -0,0 +1,189 @@
+use std::ffi::OsStr;
+use std::io;
+use std::process::{Child, Command, Output, Stdio};
+
+// Commands whose output drives the tray (warp-cli, theme probes) go through
+// a CommandRunner so tests can hand back canned output instead
+pub trait CommandRunner {
+    fn run(&self, program: &OsStr, args: &[&str]) -> io::Result<Output>;
+
+    // Starts a command that keeps running, with its stdout piped to read
+    // line by line. Runners with only canned output can't do that.
+    fn spawn(&self, program: &OsStr, args: &[&str]) -> io::Result<Child> {
+        let _ = (program, args);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this runner can't start long-running commands",
+        ))
+    }
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &OsStr, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+
+    fn spawn(&self, program: &OsStr, args: &[&str]) -> io::Result<Child> {
+        Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+    }
+}
+
+#[cfg(test)]
+pub use mock::MockRunner;
+
+#[cfg(test)]
+mod mock {
+    use super::CommandRunner;
+    use std::ffi::OsStr;
+    use std::io;
+    use std::process::{ExitStatus, Output};
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+
+    // Answers "program arg1 arg2" command lines from a fixed table. Anything
+    // else fails to start, like a program that isn't installed.
+    #[derive(Default)]
+    pub struct MockRunner {
+        outputs: Vec<(String, i32, String)>,
+    }
+
+    impl MockRunner {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with(mut self, command_line: &str, code: i32, stdout: &str) -> Self {
+            self.outputs
+                .push((command_line.to_string(), code, stdout.to_string()));
+            self
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn run(&self, program: &OsStr, args: &[&str]) -> io::Result<Output> {
+            let command_line = [program.to_string_lossy().as_ref()]
+                .into_iter()
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let (_, code, stdout) = self
+                .outputs
+                .iter()
+                .find(|(line, _, _)| *line == command_line)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, command_line))?;
+            Ok(Output {
+                status: exit_status(*code),
+                stdout: stdout.clone().into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+pub use fake::FakeWarpCli;
+
+#[cfg(all(test, unix))]
+mod fake {
+    use super::CommandRunner;
+    use std::ffi::{OsStr, OsString};
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process::{self, Child, Command, Output, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    // A shell script in a temp dir standing in for the warp-cli at `program`.
+    // It prints the same stdout for any command and appends each command line
+    // it gets to a file, so tests can check what really ran. Any other
+    // program fails to start, like one that isn't installed.
+    pub struct FakeWarpCli {
+        program: OsString,
+        dir: PathBuf,
+    }
+
+    impl FakeWarpCli {
+        pub fn install(program: &OsStr, stdout: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "warp-taskbar-test-{}-{}",
+                process::id(),
+                NEXT_ID.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&dir).expect("Failed to create the fake warp-cli dir");
+            let script = format!(
+                "#!/bin/sh\nprintf '%s\\n' \"$*\" >> '{}'\nprintf '%s' '{}'\n",
+                dir.join("calls").display(),
+                stdout.replace('\'', r"'\''")
+            );
+            let path = dir.join("warp-cli");
+            fs::write(&path, script).expect("Failed to write the fake warp-cli");
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .expect("Failed to make the fake warp-cli executable");
+            FakeWarpCli {
+                program: program.to_owned(),
+                dir,
+            }
+        }
+
+        // Arguments of each run, in order
+        pub fn calls(&self) -> Vec<String> {
+            fs::read_to_string(self.dir.join("calls"))
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl FakeWarpCli {
+        fn command(&self, program: &OsStr, args: &[&str]) -> io::Result<Command> {
+            if program != self.program {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    program.to_string_lossy(),
+                ));
+            }
+            let mut command = Command::new(self.dir.join("warp-cli"));
+            command.args(args);
+            Ok(command)
+        }
+    }
+
+    impl CommandRunner for FakeWarpCli {
+        fn run(&self, program: &OsStr, args: &[&str]) -> io::Result<Output> {
+            self.command(program, args)?.output()
+        }
+
+        fn spawn(&self, program: &OsStr, args: &[&str]) -> io::Result<Child> {
+            self.command(program, args)?
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+        }
+    }
+
+    impl Drop for FakeWarpCli {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
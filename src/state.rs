@@ -0,0 +1,68 @@
+use log::warn;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Decided on first use. Tests set it first, since they run commands through
+// the tray's own code, which would otherwise log them to the user's history.
+static STATE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+#[cfg(all(test, unix))]
+pub fn use_state_dir(dir: PathBuf) {
+    let _ = STATE_DIR.set(Some(dir));
+}
+
+pub fn state_dir() -> Option<PathBuf> {
+    STATE_DIR.get_or_init(default_state_dir).clone()
+}
+
+// Files kept between runs live in $XDG_STATE_HOME/warp-taskbar
+fn default_state_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("warp-taskbar")),
+        _ => env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join(".local")
+                .join("state")
+                .join("warp-taskbar")
+        }),
+    }
+}
+
+// Each value is a one-line file of its own in the state dir
+fn load(name: &str) -> Option<String> {
+    let content = fs::read_to_string(state_dir()?.join(name)).ok()?;
+    Some(content.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+fn save(name: &str, value: &str) {
+    let Some(path) = state_dir().map(|dir| dir.join(name)) else {
+        return;
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, format!("{}\n", value)));
+    if let Err(e) = result {
+        warn!("Failed to save {}: {}", path.display(), e);
+    }
+}
+
+// The `warp-cli set-mode` argument last picked from the menu
+pub fn load_last_mode() -> Option<String> {
+    load("last-mode")
+}
+
+pub fn save_last_mode(mode: &str) {
+    save("last-mode", mode);
+}
+
+// The warp-cli version found at the last start, to notice upgrades
+pub fn load_warp_cli_version() -> Option<String> {
+    load("warp-cli-version")
+}
+
+pub fn save_warp_cli_version(version: &str) {
+    save("warp-cli-version", version);
+}
@@ -0,0 +1,484 @@
+use crate::config::ThemeOverride;
+use crate::runner::{CommandRunner, SystemRunner};
+#[cfg(all(unix, not(target_os = "macos")))]
+use gtk::prelude::*;
+use log::debug;
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::env;
+use std::ffi::OsStr;
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::fs;
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Ask GTK itself: an explicit dark preference, a dark theme name, or a theme
+// whose background color is dark. Only a dark answer is trusted, since a light
+// GTK theme is common on desktops that signal dark mode elsewhere.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn gtk_prefers_dark() -> bool {
+    let Some(settings) = gtk::Settings::default() else {
+        return false;
+    };
+    if settings.is_gtk_application_prefer_dark_theme() {
+        return true;
+    }
+    if settings
+        .gtk_theme_name()
+        .is_some_and(|name| name.to_lowercase().contains("dark"))
+    {
+        return true;
+    }
+
+    let probe = gtk::Label::new(None);
+    match probe.style_context().lookup_color("theme_bg_color") {
+        Some(color) => {
+            let luminance = 0.2126 * color.red() + 0.7152 * color.green() + 0.0722 * color.blue();
+            luminance < 0.5
+        }
+        None => false,
+    }
+}
+
+// Classify the BackgroundNormal=r,g,b color of the [Colors:View] section of
+// kdeglobals by perceived luminance. None if the color can't be read.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn kde_view_background_is_dark(content: &str) -> Option<bool> {
+    let mut in_view_section = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_view_section = line == "[Colors:View]";
+            continue;
+        }
+        if !in_view_section {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("BackgroundNormal=") {
+            let channels = value
+                .split(',')
+                .map(|channel| channel.trim().parse::<u8>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let [r, g, b] = channels.get(..3)? else {
+                return None;
+            };
+            let luminance =
+                (0.2126 * f64::from(*r) + 0.7152 * f64::from(*g) + 0.0722 * f64::from(*b)) / 255.0;
+            return Some(luminance < 0.5);
+        }
+    }
+    None
+}
+
+// A dark view background, or failing that a Breeze Dark scheme name
+#[cfg(all(unix, not(target_os = "macos")))]
+fn kdeglobals_is_dark(content: &str) -> bool {
+    kde_view_background_is_dark(content) == Some(true)
+        || content.contains("ColorScheme=BreezeDark")
+        || content.contains("name=Breeze Dark")
+}
+
+// The portal returns the value wrapped in variants, e.g. "(<<uint32 1>>,)"
+#[cfg(all(unix, not(target_os = "macos")))]
+fn parse_portal_color_scheme(output: &str) -> Option<u32> {
+    let value = output.split("uint32").nth(1)?.trim_start();
+    let digits: String = value.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+// stdout of a settings query; None if the tool isn't installed
+#[cfg(all(unix, not(target_os = "macos")))]
+fn query(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Option<String> {
+    let output = runner.run(OsStr::new(program), args).ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn is_dark_mode_enabled() -> bool {
+    // Check GTK's own settings first
+    gtk_prefers_dark() || desktop_prefers_dark(&SystemRunner, env::var_os("HOME").as_deref())
+}
+
+// The cross-desktop xdg-desktop-portal preference, which also covers wlroots
+// compositors like sway and Hyprland: 1 = prefer dark, 2 = prefer light,
+// 0 = no preference
+#[cfg(all(unix, not(target_os = "macos")))]
+fn portal_prefers_dark(runner: &dyn CommandRunner) -> Option<bool> {
+    let stdout = query(
+        runner,
+        "gdbus",
+        &[
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.portal.Desktop",
+            "--object-path",
+            "/org/freedesktop/portal/desktop",
+            "--method",
+            "org.freedesktop.portal.Settings.Read",
+            "org.freedesktop.appearance",
+            "color-scheme",
+        ],
+    )?;
+    match parse_portal_color_scheme(&stdout)? {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    }
+}
+
+// GNOME's color-scheme; 'default' leaves it to the theme name
+#[cfg(all(unix, not(target_os = "macos")))]
+fn gnome_prefers_dark(runner: &dyn CommandRunner) -> Option<bool> {
+    let stdout = query(
+        runner,
+        "gsettings",
+        &["get", "org.gnome.desktop.interface", "color-scheme"],
+    )?;
+    if stdout.contains("prefer-dark") {
+        Some(true)
+    } else if stdout.contains("prefer-light") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// kdeglobals is also left behind by KDE apps on other desktops, so only a
+// dark scheme counts as an answer
+#[cfg(all(unix, not(target_os = "macos")))]
+fn kde_prefers_dark(home: Option<&OsStr>) -> Option<bool> {
+    let path = Path::new(home?).join(".config").join("kdeglobals");
+    let content = fs::read_to_string(path).ok()?;
+    kdeglobals_is_dark(&content).then_some(true)
+}
+
+// A setting that names a dark theme; anything else is no answer
+#[cfg(all(unix, not(target_os = "macos")))]
+fn names_dark_theme(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Option<bool> {
+    query(runner, program, args)?
+        .to_lowercase()
+        .contains("dark")
+        .then_some(true)
+}
+
+// Ask the desktop environment's own settings, standardized ones first, and
+// stop at the first definite answer so later checks aren't spawned at all
+#[cfg(all(unix, not(target_os = "macos")))]
+fn desktop_prefers_dark(runner: &dyn CommandRunner, home: Option<&OsStr>) -> bool {
+    portal_prefers_dark(runner)
+        .or_else(|| gnome_prefers_dark(runner))
+        .or_else(|| kde_prefers_dark(home))
+        // XFCE
+        .or_else(|| {
+            names_dark_theme(
+                runner,
+                "xfconf-query",
+                &["-c", "xsettings", "-p", "/Net/ThemeName"],
+            )
+        })
+        // Cinnamon
+        .or_else(|| {
+            names_dark_theme(
+                runner,
+                "gsettings",
+                &["get", "org.cinnamon.desktop.interface", "gtk-theme"],
+            )
+        })
+        // MATE
+        .or_else(|| {
+            names_dark_theme(
+                runner,
+                "gsettings",
+                &["get", "org.mate.interface", "gtk-theme"],
+            )
+        })
+        // Elementary OS
+        .or_else(|| {
+            query(
+                runner,
+                "gsettings",
+                &[
+                    "get",
+                    "io.elementary.terminal.settings",
+                    "prefer-dark-style",
+                ],
+            )?
+            .contains("true")
+            .then_some(true)
+        })
+        // Fallback: the GTK theme name in general
+        .or_else(|| {
+            names_dark_theme(
+                runner,
+                "gsettings",
+                &["get", "org.gnome.desktop.interface", "gtk-theme"],
+            )
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+pub fn is_dark_mode_enabled() -> bool {
+    system_prefers_dark(&SystemRunner)
+}
+
+// macOS only sets AppleInterfaceStyle while dark mode is on; in light mode
+// reading it fails
+#[cfg(target_os = "macos")]
+fn system_prefers_dark(runner: &dyn CommandRunner) -> bool {
+    runner
+        .run(
+            OsStr::new("defaults"),
+            &["read", "-g", "AppleInterfaceStyle"],
+        )
+        .is_ok_and(|output| {
+            output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "Dark"
+        })
+}
+
+// Windows keeps the taskbar theme in the registry; 0x0 means dark
+#[cfg(windows)]
+fn system_prefers_dark(runner: &dyn CommandRunner) -> bool {
+    runner
+        .run(
+            OsStr::new("reg"),
+            &[
+                "query",
+                r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+                "/v",
+                "SystemUsesLightTheme",
+            ],
+        )
+        .is_ok_and(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .last()
+                == Some("0x0")
+        })
+}
+
+// GNOME Shell draws tray icons as monochrome symbols, so colored ones look
+// out of place there. XDG_CURRENT_DESKTOP may list several names, e.g.
+// "ubuntu:GNOME".
+#[cfg(all(unix, not(target_os = "macos")))]
+fn desktop_uses_symbolic_icons(current_desktop: &str) -> bool {
+    current_desktop
+        .split(':')
+        .any(|desktop| desktop.eq_ignore_ascii_case("gnome"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn prefers_symbolic_icons() -> bool {
+    env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktop| desktop_uses_symbolic_icons(&desktop))
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+pub fn prefers_symbolic_icons() -> bool {
+    false
+}
+
+const THEME_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Theme detection spawns several subprocesses, so the poll loop reads the
+// result from here and it is only re-detected every THEME_REFRESH_INTERVAL.
+// A light/dark override skips detection entirely.
+pub struct ThemeCache {
+    theme_override: ThemeOverride,
+    is_dark: bool,
+    checked_at: Instant,
+}
+
+impl ThemeCache {
+    pub fn new(theme_override: ThemeOverride) -> Self {
+        let mut cache = ThemeCache {
+            theme_override,
+            is_dark: false,
+            checked_at: Instant::now(),
+        };
+        cache.refresh();
+        cache
+    }
+
+    pub fn is_dark(&mut self) -> bool {
+        if self.theme_override == ThemeOverride::Auto
+            && self.checked_at.elapsed() >= THEME_REFRESH_INTERVAL
+        {
+            self.refresh();
+        }
+        self.is_dark
+    }
+
+    pub fn refresh(&mut self) {
+        self.is_dark = match self.theme_override {
+            ThemeOverride::Light => false,
+            ThemeOverride::Dark => true,
+            ThemeOverride::Auto => is_dark_mode_enabled(),
+        };
+        debug!(
+            "Theme is {} ({:?})",
+            if self.is_dark { "dark" } else { "light" },
+            self.theme_override
+        );
+        self.checked_at = Instant::now();
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use crate::runner::MockRunner;
+
+    const BREEZE_DARK: &str = "\
+[General]
+ColorScheme=BreezeDark
+
+[Colors:View]
+BackgroundAlternate=35,38,41
+BackgroundNormal=27,30,32
+ForegroundNormal=252,252,252
+";
+
+    const BREEZE_LIGHT: &str = "\
+[General]
+ColorScheme=BreezeLight
+
+[Colors:Window]
+BackgroundNormal=20,20,20
+
+[Colors:View]
+BackgroundNormal=255,255,255
+ForegroundNormal=35,38,39
+";
+
+    const CUSTOM_DARK: &str = "\
+[General]
+ColorScheme=Nord
+
+[Colors:View]
+BackgroundNormal=46,52,64
+";
+
+    #[test]
+    fn breeze_dark_is_dark() {
+        assert_eq!(kde_view_background_is_dark(BREEZE_DARK), Some(true));
+        assert!(kdeglobals_is_dark(BREEZE_DARK));
+    }
+
+    #[test]
+    fn breeze_light_is_light() {
+        // The dark [Colors:Window] background must not be picked up
+        assert_eq!(kde_view_background_is_dark(BREEZE_LIGHT), Some(false));
+        assert!(!kdeglobals_is_dark(BREEZE_LIGHT));
+    }
+
+    #[test]
+    fn custom_dark_scheme_is_dark() {
+        assert_eq!(kde_view_background_is_dark(CUSTOM_DARK), Some(true));
+        assert!(kdeglobals_is_dark(CUSTOM_DARK));
+    }
+
+    #[test]
+    fn malformed_rgb_is_unreadable() {
+        for color in ["abc,def,ghi", "255,255", "300,0,0", ""] {
+            let content = format!("[Colors:View]\nBackgroundNormal={}\n", color);
+            assert_eq!(kde_view_background_is_dark(&content), None, "{}", color);
+            assert!(!kdeglobals_is_dark(&content));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_scheme_name() {
+        let content = "[General]\nColorScheme=BreezeDark\n";
+        assert_eq!(kde_view_background_is_dark(content), None);
+        assert!(kdeglobals_is_dark(content));
+    }
+
+    #[test]
+    fn portal_dark_preference_is_dark() {
+        let runner = MockRunner::new().with(
+            "gdbus call --session --dest org.freedesktop.portal.Desktop \
+             --object-path /org/freedesktop/portal/desktop \
+             --method org.freedesktop.portal.Settings.Read \
+             org.freedesktop.appearance color-scheme",
+            0,
+            "(<<uint32 1>>,)\n",
+        );
+        assert!(desktop_prefers_dark(&runner, None));
+    }
+
+    #[test]
+    fn gnome_color_scheme_is_dark() {
+        let runner = MockRunner::new().with(
+            "gsettings get org.gnome.desktop.interface color-scheme",
+            0,
+            "'prefer-dark'\n",
+        );
+        assert!(desktop_prefers_dark(&runner, None));
+    }
+
+    #[test]
+    fn light_settings_are_light() {
+        let runner = MockRunner::new()
+            .with(
+                "gsettings get org.gnome.desktop.interface color-scheme",
+                0,
+                "'default'\n",
+            )
+            .with(
+                "gsettings get org.gnome.desktop.interface gtk-theme",
+                0,
+                "'Adwaita'\n",
+            );
+        assert!(!desktop_prefers_dark(&runner, None));
+    }
+
+    #[test]
+    fn missing_tools_are_light() {
+        assert!(!desktop_prefers_dark(&MockRunner::new(), None));
+    }
+
+    #[test]
+    fn portal_light_preference_stops_the_search() {
+        let runner = MockRunner::new()
+            .with(
+                "gdbus call --session --dest org.freedesktop.portal.Desktop \
+                 --object-path /org/freedesktop/portal/desktop \
+                 --method org.freedesktop.portal.Settings.Read \
+                 org.freedesktop.appearance color-scheme",
+                0,
+                "(<<uint32 2>>,)\n",
+            )
+            .with(
+                "gsettings get org.gnome.desktop.interface gtk-theme",
+                0,
+                "'Adwaita-dark'\n",
+            );
+        assert!(!desktop_prefers_dark(&runner, None));
+    }
+
+    #[test]
+    fn default_color_scheme_falls_through_to_the_theme_name() {
+        let runner = MockRunner::new()
+            .with(
+                "gsettings get org.gnome.desktop.interface color-scheme",
+                0,
+                "'default'\n",
+            )
+            .with(
+                "gsettings get org.gnome.desktop.interface gtk-theme",
+                0,
+                "'Adwaita-dark'\n",
+            );
+        assert!(desktop_prefers_dark(&runner, None));
+    }
+
+    #[test]
+    fn gnome_sessions_use_symbolic_icons() {
+        assert!(desktop_uses_symbolic_icons("GNOME"));
+        assert!(desktop_uses_symbolic_icons("ubuntu:GNOME"));
+        assert!(!desktop_uses_symbolic_icons("KDE"));
+        assert!(!desktop_uses_symbolic_icons("X-Cinnamon"));
+        assert!(!desktop_uses_symbolic_icons(""));
+    }
+}